@@ -0,0 +1,340 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use parking_lot::Mutex;
+use symphonia::core::{
+    audio::SampleBuffer, codecs::DecoderOptions, errors::Error as DecodeError,
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+use crate::{audio_analysis::FFT_SIZE, Analyzer, AudioAnalysis, TextureData};
+
+pub const AUDIO_EXTENSIONS: [&str; 3] = ["wav", "ogg", "flac"];
+
+/// Playback drifting further than this from the timeline seeks back to it.
+const SYNC_THRESHOLD: f64 = 0.1;
+
+/// Signal analysed into the audio texture and uniforms.
+pub trait AudioSource {
+    /// Writes the mono samples right before `time` into `out`, oldest first.
+    /// Live sources ignore the timeline and return the latest samples.
+    fn read(&mut self, time: f32, playing: bool, out: &mut [f32]);
+    fn sample_rate(&self) -> u32;
+    fn name(&self) -> String;
+    /// File the source plays, if any.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Fully decoded audio file with interleaved samples.
+#[derive(Clone)]
+pub struct AudioClip {
+    pub samples: Arc<[f32]>,
+    pub channels: usize,
+    pub sample_rate: u32,
+}
+
+impl AudioClip {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(ext);
+        }
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let mut format = probed.format;
+        let Some(track) = format.default_track() else {
+            bail!("{} has no audio track", path.display());
+        };
+        let track_id = track.id;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        let mut samples = vec![];
+        let (mut channels, mut sample_rate) = (0, 0);
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(DecodeError::IoError(err))
+                    if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(DecodeError::DecodeError(err)) => {
+                    log::warn!("Skipping a corrupted packet of {}: {err}", path.display());
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let spec = *decoded.spec();
+            channels = spec.channels.count();
+            sample_rate = spec.rate;
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as _, spec);
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+        if samples.is_empty() || channels == 0 {
+            bail!("{} has no samples", path.display());
+        }
+
+        Ok(Self {
+            samples: samples.into(),
+            channels,
+            sample_rate,
+        })
+    }
+
+    /// Writes the clip as a 16-bit PCM WAV file.
+    pub fn write_wav(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        let channels = self.channels as u16;
+        let block_align = channels * 2;
+        let data_size = self.samples.len() as u32 * 2;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(36 + data_size).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&data_size.to_le_bytes())?;
+        for sample in self.samples.iter() {
+            let sample = (sample.clamp(-1., 1.) * i16::MAX as f32) as i16;
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    /// Mono mix of `out.len()` frames ending at `time`, silence outside of the clip.
+    pub fn mono_window(&self, time: f32, out: &mut [f32]) {
+        let end = (time as f64 * self.sample_rate as f64) as i64;
+        let start = end - out.len() as i64;
+        for (i, sample) in out.iter_mut().enumerate() {
+            let frame = start + i as i64;
+            *sample = if frame < 0 || frame as usize >= self.frame_count() {
+                0.
+            } else {
+                let frame = frame as usize * self.channels;
+                self.samples[frame..frame + self.channels]
+                    .iter()
+                    .sum::<f32>()
+                    / self.channels as f32
+            };
+        }
+    }
+}
+
+struct PlaybackState {
+    /// Position in frames of the clip, fractional when the output rate differs.
+    position: f64,
+    playing: bool,
+}
+
+/// Output stream of the default audio device playing a clip.
+pub struct AudioPlayer {
+    state: Arc<Mutex<PlaybackState>>,
+    sample_rate: u32,
+    _stream: cpal::Stream,
+}
+
+impl AudioPlayer {
+    pub fn new(clip: &AudioClip) -> Result<Self> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .context("No audio output device")?;
+        let config = device.default_output_config()?;
+        let state = Arc::new(Mutex::new(PlaybackState {
+            position: 0.,
+            playing: false,
+        }));
+
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config.config(), clip, &state),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config.config(), clip, &state),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config.config(), clip, &state),
+            format => bail!("Unsupported output sample format {format}"),
+        }?;
+        stream.play()?;
+
+        Ok(Self {
+            state,
+            sample_rate: clip.sample_rate,
+            _stream: stream,
+        })
+    }
+
+    /// Follows the timeline, seeking when playback drifted away from `time`.
+    pub fn sync(&self, time: f32, playing: bool) {
+        let mut state = self.state.lock();
+        let target = time as f64 * self.sample_rate as f64;
+        let drift = (state.position - target).abs() / self.sample_rate as f64;
+        if !playing || drift > SYNC_THRESHOLD {
+            state.position = target;
+        }
+        state.playing = playing;
+    }
+}
+
+fn build_stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    clip: &AudioClip,
+    state: &Arc<Mutex<PlaybackState>>,
+) -> Result<cpal::Stream> {
+    let output_channels = config.channels as usize;
+    let step = clip.sample_rate as f64 / config.sample_rate.0 as f64;
+    let (samples, channels) = (clip.samples.clone(), clip.channels);
+    let frame_count = samples.len() / channels;
+    let state = state.clone();
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut state = state.lock();
+            for frame in data.chunks_mut(output_channels) {
+                let position = state.position;
+                let (index, t) = (position as usize, position.fract() as f32);
+                let playing = state.playing && position >= 0. && index + 1 < frame_count;
+                for (channel, out) in frame.iter_mut().enumerate() {
+                    let sample = if playing {
+                        let channel = channel % channels;
+                        let a = samples[index * channels + channel];
+                        let b = samples[(index + 1) * channels + channel];
+                        a + (b - a) * t
+                    } else {
+                        0.
+                    };
+                    *out = T::from_sample(sample);
+                }
+                if state.playing {
+                    state.position += step;
+                }
+            }
+        },
+        |err| log::error!("Audio output: {err}"),
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Audio file played along the timeline.
+pub struct FileSource {
+    path: PathBuf,
+    clip: AudioClip,
+    /// Missing without an output device, the analysis still follows the timeline.
+    player: Option<AudioPlayer>,
+}
+
+impl FileSource {
+    pub fn new(path: &Path) -> Result<Self> {
+        Ok(Self::from_clip(path, AudioClip::load(path)?))
+    }
+
+    /// Plays a clip made in memory, `path` is the file it comes from.
+    pub fn from_clip(path: &Path, clip: AudioClip) -> Self {
+        let player = AudioPlayer::new(&clip)
+            .map_err(|err| log::warn!("Playing {} silently: {err:#}", path.display()))
+            .ok();
+        Self {
+            path: path.to_path_buf(),
+            clip,
+            player,
+        }
+    }
+}
+
+impl AudioSource for FileSource {
+    /// Keeps playback in sync with the timeline.
+    fn read(&mut self, time: f32, playing: bool, out: &mut [f32]) {
+        if let Some(player) = &self.player {
+            player.sync(time, playing);
+        }
+        self.clip.mono_window(time, out);
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.clip.sample_rate
+    }
+
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// Audio source analysed into a texture slot every frame.
+pub struct AudioTrack {
+    pub slot: u32,
+    source: Box<dyn AudioSource>,
+    analyzer: Analyzer,
+    samples: Vec<f32>,
+}
+
+impl AudioTrack {
+    pub fn new(source: Box<dyn AudioSource>, slot: u32) -> Self {
+        Self {
+            slot,
+            source,
+            analyzer: Analyzer::new(),
+            samples: vec![0.; FFT_SIZE],
+        }
+    }
+
+    pub fn source(&self) -> &dyn AudioSource {
+        self.source.as_ref()
+    }
+
+    /// Silent analysis with the layout of the track's texture.
+    pub fn empty_texture() -> TextureData {
+        AudioAnalysis::silence().texture_data()
+    }
+
+    /// Analyses the samples of the source before `time`, `dt` after the previous update.
+    pub fn update(&mut self, time: f32, playing: bool, dt: f32) -> AudioAnalysis {
+        self.source.read(time, playing, &mut self.samples);
+        let sample_rate = self.source.sample_rate();
+        self.analyzer.process(&self.samples, sample_rate, dt)
+    }
+}
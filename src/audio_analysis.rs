@@ -0,0 +1,292 @@
+use std::sync::Arc;
+
+use ash::vk;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::TextureData;
+
+/// Width of the spectrum and waveform rows, the layout of Shadertoy's audio inputs.
+pub const AUDIO_TEXTURE_WIDTH: u32 = 512;
+/// Samples analysed at once, sources keep at least this many around.
+pub const FFT_SIZE: usize = 2048;
+/// Decibel range mapped onto [0, 1], the defaults of WebAudio's `AnalyserNode`.
+const MIN_DB: f32 = -100.;
+const MAX_DB: f32 = -30.;
+/// Share of the previous spectrum kept after 1/60 of a second.
+const SMOOTHING: f32 = 0.8;
+/// Seconds a peak of the level and bands stays before it starts to fall.
+const PEAK_HOLD: f32 = 0.1;
+/// Fall of a held peak per second.
+const PEAK_DECAY: f32 = 1.5;
+
+const BASS: (f32, f32) = (20., 250.);
+const MID: (f32, f32) = (250., 4000.);
+const TREBLE: (f32, f32) = (4000., 16000.);
+
+/// Spectrum and waveform of the latest samples of a source.
+#[derive(Debug, Clone)]
+pub struct AudioAnalysis {
+    /// Smoothed magnitudes of the lower half of the spectrum mapped from decibels onto [0, 1].
+    pub spectrum: Vec<f32>,
+    /// Latest samples mapped from [-1, 1] onto [0, 1].
+    pub waveform: Vec<f32>,
+    /// RMS of the analysed window, the level and the bands hold their peaks.
+    pub level: f32,
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+    /// Rise of the unsmoothed spectrum since the previous call, spikes on onsets.
+    pub flux: f32,
+}
+
+impl AudioAnalysis {
+    pub fn silence() -> Self {
+        let width = AUDIO_TEXTURE_WIDTH as usize;
+        Self {
+            spectrum: vec![0.; width],
+            waveform: vec![0.5; width],
+            level: 0.,
+            bass: 0.,
+            mid: 0.,
+            treble: 0.,
+            flux: 0.,
+        }
+    }
+
+    /// 512x2 texture with the spectrum in the first row and the waveform in the second.
+    pub fn texture_data(&self) -> TextureData {
+        let texels: Vec<u8> = self
+            .spectrum
+            .iter()
+            .chain(&self.waveform)
+            .map(|v| (v * 255.).round().clamp(0., 255.) as u8)
+            .collect();
+        let extent = vk::Extent3D {
+            width: AUDIO_TEXTURE_WIDTH,
+            height: 2,
+            depth: 1,
+        };
+        TextureData::from_levels(vk::Format::R8_UNORM, extent, [&texels[..]])
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Peak {
+    value: f32,
+    hold: f32,
+}
+
+impl Peak {
+    fn update(&mut self, value: f32, dt: f32) -> f32 {
+        if value >= self.value {
+            self.value = value;
+            self.hold = PEAK_HOLD;
+        } else if self.hold > 0. {
+            self.hold -= dt;
+        } else {
+            self.value = (self.value - PEAK_DECAY * dt).max(value);
+        }
+        self.value
+    }
+}
+
+/// Hann windowed FFT with the smoothing of WebAudio's `AnalyserNode`, shared by every
+/// audio source. It only sees sample buffers, so any signal can be fed to it.
+pub struct Analyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+    /// Unsmoothed spectrum of the previous call in [0, 1].
+    previous: Vec<f32>,
+    peaks: [Peak; 4],
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = std::f32::consts::TAU * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            buffer: vec![Complex::default(); FFT_SIZE],
+            magnitudes: vec![0.; FFT_SIZE / 2],
+            previous: vec![0.; FFT_SIZE / 2],
+            peaks: [Peak::default(); 4],
+        }
+    }
+
+    /// Analyses the last `FFT_SIZE` mono samples, shorter buffers are padded with silence.
+    /// Smoothing and peaks carry over from the previous call made `dt` seconds ago.
+    pub fn process(&mut self, samples: &[f32], sample_rate: u32, dt: f32) -> AudioAnalysis {
+        let samples = &samples[samples.len().saturating_sub(FFT_SIZE)..];
+        let padding = FFT_SIZE - samples.len();
+        for (i, value) in self.buffer.iter_mut().enumerate() {
+            let sample = i.checked_sub(padding).map_or(0., |i| samples[i]);
+            *value = Complex::new(sample * self.window[i], 0.);
+        }
+        self.fft.process(&mut self.buffer);
+
+        let smoothing = SMOOTHING.powf(dt.max(0.) * 60.);
+        let mut flux = 0.;
+        for ((magnitude, previous), value) in self
+            .magnitudes
+            .iter_mut()
+            .zip(&mut self.previous)
+            .zip(&self.buffer)
+        {
+            let current = value.norm() / FFT_SIZE as f32;
+            let level = normalize(current);
+            flux += (level - *previous).max(0.);
+            *previous = level;
+            *magnitude = smoothing * *magnitude + (1. - smoothing) * current;
+        }
+        let normalized: Vec<f32> = self.magnitudes.iter().map(|&m| normalize(m)).collect();
+        let band = |(low, high): (f32, f32)| {
+            let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+            let start = ((low / bin_width).ceil() as usize).min(normalized.len());
+            let end = ((high / bin_width).ceil() as usize).min(normalized.len());
+            let values = &normalized[start..end];
+            if values.is_empty() {
+                0.
+            } else {
+                values.iter().sum::<f32>() / values.len() as f32
+            }
+        };
+
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32).sqrt();
+        let values = [
+            (rms * std::f32::consts::SQRT_2).min(1.),
+            band(BASS),
+            band(MID),
+            band(TREBLE),
+        ];
+        let [level, bass, mid, treble] =
+            std::array::from_fn(|i| self.peaks[i].update(values[i], dt));
+
+        let width = AUDIO_TEXTURE_WIDTH as usize;
+        let waveform = (0..width)
+            .map(|i| {
+                let sample = (i + samples.len())
+                    .checked_sub(width)
+                    .map_or(0., |i| samples[i]);
+                (sample * 0.5 + 0.5).clamp(0., 1.)
+            })
+            .collect();
+        AudioAnalysis {
+            spectrum: normalized[..width].to_vec(),
+            waveform,
+            level,
+            bass,
+            mid,
+            treble,
+            flux: flux / self.previous.len() as f32,
+        }
+    }
+}
+
+/// Maps a magnitude from decibels onto [0, 1].
+fn normalize(magnitude: f32) -> f32 {
+    let db = 20. * magnitude.max(f32::MIN_POSITIVE).log10();
+    ((db - MIN_DB) / (MAX_DB - MIN_DB)).clamp(0., 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+    /// Frames of a 60 Hz display.
+    const DT: f32 = 1. / 60.;
+
+    /// A sine that fits the window exactly, so its energy stays around `bin`.
+    fn sine(bin: usize, amplitude: f32) -> Vec<f32> {
+        let frequency = bin as f32 * SAMPLE_RATE as f32 / FFT_SIZE as f32;
+        (0..FFT_SIZE)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                amplitude * (std::f32::consts::TAU * frequency * t).sin()
+            })
+            .collect()
+    }
+
+    fn loudest_bin(spectrum: &[f32]) -> usize {
+        (0..spectrum.len())
+            .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+            .unwrap()
+    }
+
+    #[test]
+    fn silence_is_zero() {
+        let mut analyzer = Analyzer::new();
+        let analysis = analyzer.process(&[0.; FFT_SIZE], SAMPLE_RATE, DT);
+        assert_eq!(analysis.level, 0.);
+        assert_eq!([analysis.bass, analysis.mid, analysis.treble], [0.; 3]);
+        assert_eq!(analysis.flux, 0.);
+        assert!(analysis.spectrum.iter().all(|&v| v == 0.));
+        assert!(analysis.waveform.iter().all(|&v| v == 0.5));
+    }
+
+    #[test]
+    fn short_buffers_are_padded() {
+        let mut analyzer = Analyzer::new();
+        let analysis = analyzer.process(&[], SAMPLE_RATE, DT);
+        assert_eq!(analysis.level, 0.);
+        assert_eq!(analysis.spectrum.len(), AUDIO_TEXTURE_WIDTH as usize);
+    }
+
+    #[test]
+    fn sine_lands_in_its_band() {
+        // 187.5 Hz, 1500 Hz and 6000 Hz
+        for (bin, band) in [(8, 0), (64, 1), (256, 2)] {
+            let mut analyzer = Analyzer::new();
+            // A whole second between calls leaves next to nothing of the smoothing,
+            // -60 dB keeps the peak below the clamped top of the range
+            let analysis = analyzer.process(&sine(bin, 0.004), SAMPLE_RATE, 1.);
+            assert_eq!(loudest_bin(&analysis.spectrum), bin);
+            assert!((analysis.level - 0.004).abs() < 1e-4, "{}", analysis.level);
+
+            let bands = [analysis.bass, analysis.mid, analysis.treble];
+            for (i, value) in bands.into_iter().enumerate() {
+                match i == band {
+                    true => assert!(value > 0., "bin {bin}: {bands:?}"),
+                    false => assert!(value < bands[band], "bin {bin}: {bands:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spectrum_decays_smoothly() {
+        let mut analyzer = Analyzer::new();
+        let bin = 64;
+        let mut last = analyzer
+            .process(&sine(bin, 0.004), SAMPLE_RATE, 1.)
+            .spectrum[bin];
+        assert!(last > 0. && last < 1., "{last}");
+        for _ in 0..10 {
+            let value = analyzer.process(&[0.; FFT_SIZE], SAMPLE_RATE, DT).spectrum[bin];
+            assert!(value > 0. && value < last, "{value} after {last}");
+            last = value;
+        }
+    }
+
+    #[test]
+    fn peaks_hold_then_fall() {
+        let mut analyzer = Analyzer::new();
+        let peak = analyzer.process(&sine(8, 0.5), SAMPLE_RATE, 1.).level;
+        let levels: Vec<f32> = (0..40)
+            .map(|_| analyzer.process(&[0.; FFT_SIZE], SAMPLE_RATE, DT).level)
+            .collect();
+
+        let held = (PEAK_HOLD / DT) as usize;
+        assert!(levels[..held].iter().all(|&level| level == peak));
+        assert!(levels.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(levels[held + 2] < peak);
+        assert_eq!(*levels.last().unwrap(), 0.);
+    }
+}
@@ -0,0 +1,101 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use parking_lot::Mutex;
+
+use crate::{audio_analysis::FFT_SIZE, AudioSource};
+
+/// Live input device, e.g. a microphone or the mixer's line out.
+pub struct CaptureSource {
+    name: String,
+    sample_rate: u32,
+    /// Latest mono samples, filled by the input stream.
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    _stream: cpal::Stream,
+}
+
+impl CaptureSource {
+    /// Opens the default input device, or the first one whose name contains `device`.
+    pub fn new(device: Option<&str>) -> Result<Self> {
+        let host = cpal::default_host();
+        let device = match device {
+            Some(pattern) => host
+                .input_devices()?
+                .find(|device| device.name().is_ok_and(|name| name.contains(pattern)))
+                .with_context(|| format!("No audio input device matching `{pattern}`"))?,
+            None => host
+                .default_input_device()
+                .context("No default audio input device")?,
+        };
+        let name = device.name().unwrap_or_else(|_| "audio input".into());
+        let config = device.default_input_config()?;
+        let samples = Arc::new(Mutex::new(VecDeque::with_capacity(FFT_SIZE)));
+
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config.config(), &samples),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config.config(), &samples),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config.config(), &samples),
+            format => bail!("Unsupported input sample format {format}"),
+        }?;
+        stream.play()?;
+        log::info!("Capturing audio from {name}");
+
+        Ok(Self {
+            name,
+            sample_rate: config.sample_rate().0,
+            samples,
+            _stream: stream,
+        })
+    }
+}
+
+impl AudioSource for CaptureSource {
+    fn read(&mut self, _time: f32, _playing: bool, out: &mut [f32]) {
+        let samples = self.samples.lock();
+        let padding = out.len().saturating_sub(samples.len());
+        out[..padding].fill(0.);
+        let latest = samples.range(samples.len() + padding - out.len()..);
+        for (out, &sample) in out[padding..].iter_mut().zip(latest) {
+            *out = sample;
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+fn build_stream<T: cpal::SizedSample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: &Arc<Mutex<VecDeque<f32>>>,
+) -> Result<cpal::Stream>
+where
+    f32: cpal::FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let samples = samples.clone();
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            let mut samples = samples.lock();
+            for frame in data.chunks(channels) {
+                let sum: f32 = frame
+                    .iter()
+                    .map(|&sample| cpal::Sample::to_sample::<f32>(sample))
+                    .sum();
+                samples.push_back(sum / channels as f32);
+            }
+            let excess = samples.len().saturating_sub(FFT_SIZE);
+            samples.drain(..excess);
+        },
+        |err| log::error!("Audio input: {err}"),
+        None,
+    )?;
+    Ok(stream)
+}
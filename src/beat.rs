@@ -0,0 +1,212 @@
+use std::{collections::VecDeque, time::Instant};
+
+pub const BEATS_PER_BAR: u32 = 4;
+
+const DEFAULT_BPM: f64 = 120.;
+/// Detected tempos get folded into this range, halves and doubles are indistinguishable.
+const MIN_BPM: f64 = 80.;
+const MAX_BPM: f64 = 160.;
+/// Seconds of onsets the tempo is estimated from.
+const ONSET_WINDOW: f32 = 8.;
+/// Seconds of flux the onset threshold adapts to.
+const FLUX_WINDOW: f32 = 1.;
+/// Shortest gap between two onsets.
+const MIN_ONSET_GAP: f32 = 0.1;
+/// Share of the phase error to an onset near a beat corrected per onset.
+const PHASE_CORRECTION: f64 = 0.2;
+/// Taps further apart than this start a new tempo.
+const MAX_TAP_GAP: f64 = 2.;
+/// Decay rate of the beat envelope per second.
+const ENVELOPE_DECAY: f32 = 8.;
+
+/// Beat state of the current frame, as seen by shaders.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Beat {
+    pub bpm: f32,
+    /// Position inside the current beat in [0, 1).
+    pub phase: f32,
+    /// Beats since the start of the timeline.
+    pub count: u32,
+    /// 1 on a beat, decaying exponentially until the next one.
+    pub envelope: f32,
+    /// Position inside the current bar of [`BEATS_PER_BAR`] beats in [0, 1).
+    pub bar: f32,
+}
+
+/// Follows the tempo of the audio onsets on the timeline, or the tempo tapped by hand.
+pub struct BeatTracker {
+    bpm: f64,
+    /// Beats since the start of the timeline, the fraction is the phase.
+    position: f64,
+    last_time: f32,
+    flux: VecDeque<(f32, f32)>,
+    onsets: VecDeque<f32>,
+    taps: Vec<Instant>,
+    /// Set by tapping, the detected tempo is ignored until [`BeatTracker::unlock`].
+    locked: bool,
+}
+
+impl BeatTracker {
+    pub fn new() -> Self {
+        Self {
+            bpm: DEFAULT_BPM,
+            position: 0.,
+            last_time: 0.,
+            flux: VecDeque::new(),
+            onsets: VecDeque::new(),
+            taps: vec![],
+            locked: false,
+        }
+    }
+
+    /// Advances the beat to `time`, `flux` comes from the audio analysis if there is any.
+    pub fn update(&mut self, time: f32, flux: Option<f32>) -> Beat {
+        let dt = time - self.last_time;
+        self.last_time = time;
+        self.position += dt as f64 * self.bpm / 60.;
+        // Seeking makes the onset history meaningless
+        if !(0. ..1.).contains(&dt) {
+            self.flux.clear();
+            self.onsets.clear();
+        }
+
+        if let Some(flux) = flux.filter(|_| dt > 0.) {
+            if self.is_onset(time, flux) {
+                self.onset(time);
+            }
+        }
+        self.beat()
+    }
+
+    fn is_onset(&mut self, time: f32, flux: f32) -> bool {
+        while self
+            .flux
+            .front()
+            .is_some_and(|&(t, _)| t < time - FLUX_WINDOW)
+        {
+            self.flux.pop_front();
+        }
+        let count = self.flux.len().max(1) as f32;
+        let mean = self.flux.iter().map(|(_, f)| f).sum::<f32>() / count;
+        let variance = self
+            .flux
+            .iter()
+            .map(|(_, f)| (f - mean).powi(2))
+            .sum::<f32>()
+            / count;
+        self.flux.push_back((time, flux));
+
+        let threshold = mean + 1.5 * variance.sqrt() + 0.01;
+        let rested = self
+            .onsets
+            .back()
+            .is_none_or(|&last| time - last >= MIN_ONSET_GAP);
+        flux > threshold && rested
+    }
+
+    fn onset(&mut self, time: f32) {
+        self.onsets.push_back(time);
+        while self
+            .onsets
+            .front()
+            .is_some_and(|&t| t < time - ONSET_WINDOW)
+        {
+            self.onsets.pop_front();
+        }
+
+        if !self.locked {
+            if let Some(bpm) = self.estimate_bpm() {
+                // Small drifts are smoothed, a new song jumps to its tempo
+                self.bpm = if (bpm - self.bpm).abs() / self.bpm > 0.1 {
+                    bpm
+                } else {
+                    self.bpm + (bpm - self.bpm) * 0.1
+                };
+            }
+        }
+
+        // Pull the nearest beat towards the onset
+        let error = self.position - self.position.round();
+        if error.abs() < 0.25 {
+            self.position -= error * PHASE_CORRECTION;
+        }
+    }
+
+    /// Most common tempo among the intervals between every pair of onsets.
+    fn estimate_bpm(&self) -> Option<f64> {
+        if self.onsets.len() < 4 {
+            return None;
+        }
+        let bins = (MAX_BPM - MIN_BPM) as usize + 1;
+        let mut histogram = vec![0f32; bins];
+        for (i, &a) in self.onsets.iter().enumerate() {
+            for &b in self.onsets.iter().skip(i + 1) {
+                let interval = (b - a) as f64;
+                if !(0.25..=3.).contains(&interval) {
+                    continue;
+                }
+                let mut bpm = 60. / interval;
+                while bpm < MIN_BPM {
+                    bpm *= 2.;
+                }
+                while bpm > MAX_BPM {
+                    bpm /= 2.;
+                }
+                // Closer onsets are more likely to be neighbouring beats
+                histogram[(bpm - MIN_BPM).round() as usize] += 1. / interval as f32;
+            }
+        }
+
+        let score = |i: usize| {
+            histogram[i.saturating_sub(1)..(i + 2).min(bins)]
+                .iter()
+                .sum::<f32>()
+        };
+        let best = (0..bins).max_by(|&a, &b| score(a).total_cmp(&score(b)))?;
+        (score(best) > 0.).then_some(MIN_BPM + best as f64)
+    }
+
+    /// Registers a tap, a few taps in a row lock the tempo to their pace.
+    /// Every tap moves the nearest beat onto it.
+    pub fn tap(&mut self) {
+        let now = Instant::now();
+        if self
+            .taps
+            .last()
+            .is_some_and(|last| now.duration_since(*last).as_secs_f64() > MAX_TAP_GAP)
+        {
+            self.taps.clear();
+        }
+        self.taps.push(now);
+        if self.taps.len() > 8 {
+            self.taps.remove(0);
+        }
+
+        if let [first, .., last] = self.taps[..] {
+            let interval = last.duration_since(first).as_secs_f64() / (self.taps.len() - 1) as f64;
+            self.bpm = 60. / interval;
+            self.locked = true;
+        }
+        self.position = self.position.round();
+    }
+
+    /// Returns the tempo to detection after tapping.
+    pub fn unlock(&mut self) {
+        self.locked = false;
+        self.taps.clear();
+    }
+
+    pub fn beat(&self) -> Beat {
+        let position = self.position.max(0.);
+        let phase = position.fract();
+        let count = position.floor();
+        let since_beat = phase * 60. / self.bpm;
+        Beat {
+            bpm: self.bpm as f32,
+            phase: phase as f32,
+            count: count as u32,
+            envelope: (-ENVELOPE_DECAY * since_beat as f32).exp(),
+            bar: ((count % BEATS_PER_BAR as f64 + phase) / BEATS_PER_BAR as f64) as f32,
+        }
+    }
+}
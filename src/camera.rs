@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use glam::{Mat4, Vec3};
+
+use crate::{Device, DeviceBuffer, FrameGuard, Input};
+
+/// Units per second.
+const MOVE_SPEED: f32 = 2.;
+/// Radians per pixel of mouse motion.
+const LOOK_SENSITIVITY: f32 = 0.003;
+const FAST_FACTOR: f32 = 4.;
+const SLOW_FACTOR: f32 = 0.25;
+/// Keeps the basis defined when looking straight up or down.
+const MAX_PITCH: f32 = 89. * std::f32::consts::PI / 180.;
+const DEFAULT_FOV: f32 = 60. * std::f32::consts::PI / 180.;
+const DEFAULT_ORBIT_DISTANCE: f32 = 3.;
+const MIN_ORBIT_DISTANCE: f32 = 0.05;
+const NEAR: f32 = 0.01;
+const FAR: f32 = 1000.;
+
+/// Camera state shaders read through `pc.camera`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CameraUniform {
+    pub view: Mat4,
+    pub proj: Mat4,
+    pub view_proj: Mat4,
+    pub inv_view_proj: Mat4,
+    pub position: Vec3,
+    pub fov: f32,
+    pub right: Vec3,
+    pub aspect: f32,
+    pub up: Vec3,
+    pub near: f32,
+    pub forward: Vec3,
+    pub far: f32,
+}
+
+unsafe impl bytemuck::Zeroable for CameraUniform {}
+unsafe impl bytemuck::Pod for CameraUniform {}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    Fly,
+    /// Circles around `target`, movement keys zoom and pan.
+    Orbit,
+}
+
+/// Right-handed camera with Y up, looking down -Z at zero yaw and pitch.
+pub struct Camera {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+    pub mode: CameraMode,
+    pub target: Vec3,
+    pub distance: f32,
+    pub buffer: DeviceBuffer,
+}
+
+impl Camera {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let buffer = device.create_device_buffer(
+            size_of::<CameraUniform>() as _,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+        device.name_object(buffer.buffer, "Camera Buffer");
+        let mut camera = Self {
+            position: Vec3::ZERO,
+            yaw: 0.,
+            pitch: 0.,
+            fov: DEFAULT_FOV,
+            mode: CameraMode::Fly,
+            target: Vec3::ZERO,
+            distance: DEFAULT_ORBIT_DISTANCE,
+            buffer,
+        };
+        camera.reset();
+        Ok(camera)
+    }
+
+    /// Back to the origin looking down -Z, the mode stays.
+    pub fn reset(&mut self) {
+        (self.yaw, self.pitch) = (0., 0.);
+        self.distance = DEFAULT_ORBIT_DISTANCE;
+        match self.mode {
+            CameraMode::Fly => self.position = Vec3::ZERO,
+            CameraMode::Orbit => {
+                self.target = Vec3::ZERO;
+                self.position = self.target - self.forward() * self.distance;
+            }
+        }
+    }
+
+    /// Orbits around the point in front of the camera, or flies on from where it is.
+    pub fn toggle_orbit(&mut self) -> CameraMode {
+        self.mode = match self.mode {
+            CameraMode::Fly => {
+                self.target = self.position + self.forward() * self.distance;
+                CameraMode::Orbit
+            }
+            CameraMode::Orbit => CameraMode::Fly,
+        };
+        self.mode
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vec3::new(cos_pitch * sin_yaw, sin_pitch, -cos_pitch * cos_yaw)
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.forward().cross(Vec3::Y).normalize()
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.right().cross(self.forward())
+    }
+
+    /// Turns by a mouse motion in pixels.
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.yaw = (self.yaw + dx * LOOK_SENSITIVITY).rem_euclid(std::f32::consts::TAU);
+        self.pitch = (self.pitch - dy * LOOK_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+        if self.mode == CameraMode::Orbit {
+            self.position = self.target - self.forward() * self.distance;
+        }
+    }
+
+    /// Moves by the held keys over `dt` seconds, `fast` and `slow` scale the speed.
+    pub fn update(&mut self, input: &Input, fast: bool, slow: bool, dt: f32) {
+        let mut speed = MOVE_SPEED * dt;
+        if fast {
+            speed *= FAST_FACTOR;
+        }
+        if slow {
+            speed *= SLOW_FACTOR;
+        }
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let forward = axis(input.move_forward, input.move_backward);
+        let right = axis(input.move_right, input.move_left);
+        let up = axis(input.move_up, input.move_down);
+
+        match self.mode {
+            CameraMode::Fly => {
+                let direction = self.forward() * forward + self.right() * right + Vec3::Y * up;
+                self.position += direction.normalize_or_zero() * speed;
+            }
+            CameraMode::Orbit => {
+                self.distance = (self.distance - forward * speed).max(MIN_ORBIT_DISTANCE);
+                let pan = self.right() * right + self.up() * up;
+                self.target += pan.normalize_or_zero() * speed;
+                self.position = self.target - self.forward() * self.distance;
+            }
+        }
+    }
+
+    pub fn uniform(&self, aspect: f32) -> CameraUniform {
+        let (forward, right, up) = (self.forward(), self.right(), self.up());
+        let view = Mat4::look_to_rh(self.position, forward, Vec3::Y);
+        let proj = Mat4::perspective_rh(self.fov, aspect, NEAR, FAR);
+        let view_proj = proj * view;
+        CameraUniform {
+            view,
+            proj,
+            view_proj,
+            inv_view_proj: view_proj.inverse(),
+            position: self.position,
+            fov: self.fov,
+            right,
+            aspect,
+            up,
+            near: NEAR,
+            forward,
+            far: FAR,
+        }
+    }
+
+    /// Copies the camera into its buffer ahead of the frame's passes.
+    pub fn record_upload(&self, frame: &FrameGuard, aspect: f32) {
+        let uniform = self.uniform(aspect);
+        frame.update_buffer(self.buffer.buffer, bytemuck::bytes_of(&uniform));
+    }
+}
@@ -11,6 +11,8 @@ layout(location = 0) out vec4 out_color;
 
 layout(set = 0, binding = 0) uniform sampler gsamplers[];
 layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 0, binding = 2) uniform textureCube gcubes[];
+layout(set = 0, binding = 3) uniform texture3D gvolumes[];
 vec4 Tex(uint id) {
     return texture(
         nonuniformEXT(sampler2D(gtextures[id], gsamplers[LINER_SAMPL])), in_uv);
@@ -19,6 +21,64 @@ vec4 Tex(uint id, vec2 uv) {
     return texture(
         nonuniformEXT(sampler2D(gtextures[id], gsamplers[LINER_SAMPL])), uv);
 }
+vec4 TexLod(uint id, vec2 uv, float lod) {
+    return textureLod(
+        nonuniformEXT(sampler2D(gtextures[id], gsamplers[LINEAR_CLAMP_SAMPL])), uv, lod);
+}
+vec4 TexCube(uint id, vec3 dir) {
+    return texture(
+        nonuniformEXT(samplerCube(gcubes[id], gsamplers[LINER_SAMPL])), dir);
+}
+vec4 Tex3D(uint id, vec3 uvw) {
+    return texture(
+        nonuniformEXT(sampler3D(gvolumes[id], gsamplers[LINER_SAMPL])), uvw);
+}
+
+layout(std430, buffer_reference, buffer_reference_align = 4) buffer IndirectArgs {
+    uint vertex_count;
+    uint instance_count;
+    uint first_vertex;
+    uint first_instance;
+    uvec3 group_count;
+};
+
+layout(std430, buffer_reference, buffer_reference_align = 4) readonly buffer Params {
+    float values[];
+};
+
+layout(std430, buffer_reference, buffer_reference_align = 16) readonly buffer Camera {
+    mat4 view;
+    mat4 proj;
+    mat4 view_proj;
+    mat4 inv_view_proj;
+    vec3 position;
+    float fov;
+    vec3 right;
+    float aspect;
+    vec3 up;
+    float near;
+    vec3 forward;
+    float far;
+};
+
+const uint MOUSE_LEFT = 1;
+const uint MOUSE_RIGHT = 2;
+const uint MOUSE_MIDDLE = 4;
+
+// Pixels from the bottom-left corner
+layout(std430, buffer_reference, buffer_reference_align = 16) readonly buffer MouseState {
+    vec2 position;
+    vec2 normalized;
+    vec2 click;
+    vec2 click_normalized;
+    vec2 drag;
+    vec2 wheel;
+    vec4 shadertoy;
+    uint buttons;
+    uint pressed;
+    uint released;
+    uint dragging;
+};
 
 layout(std430, push_constant) uniform PushConstant {
     vec3 pos;
@@ -29,6 +89,20 @@ layout(std430, push_constant) uniform PushConstant {
     uint frame;
     float time_delta;
     float record_time;
+    IndirectArgs indirect;
+    Params params;
+    Camera camera;
+    MouseState mouse_state;
+    float level;
+    float bass;
+    float mid;
+    float treble;
+    float bpm;
+    float beat_phase;
+    uint beat_count;
+    float beat_env;
+    float bar;
+    uint _pad;
 }
 pc;
 
@@ -45,6 +119,54 @@ pub const VERT_SHADER: &str = "#version 460
 
 layout(set = 0, binding = 0) uniform sampler gsamplers[];
 layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 0, binding = 2) uniform textureCube gcubes[];
+layout(set = 0, binding = 3) uniform texture3D gvolumes[];
+
+layout(std430, buffer_reference, buffer_reference_align = 4) buffer IndirectArgs {
+    uint vertex_count;
+    uint instance_count;
+    uint first_vertex;
+    uint first_instance;
+    uvec3 group_count;
+};
+
+layout(std430, buffer_reference, buffer_reference_align = 4) readonly buffer Params {
+    float values[];
+};
+
+layout(std430, buffer_reference, buffer_reference_align = 16) readonly buffer Camera {
+    mat4 view;
+    mat4 proj;
+    mat4 view_proj;
+    mat4 inv_view_proj;
+    vec3 position;
+    float fov;
+    vec3 right;
+    float aspect;
+    vec3 up;
+    float near;
+    vec3 forward;
+    float far;
+};
+
+const uint MOUSE_LEFT = 1;
+const uint MOUSE_RIGHT = 2;
+const uint MOUSE_MIDDLE = 4;
+
+// Pixels from the bottom-left corner
+layout(std430, buffer_reference, buffer_reference_align = 16) readonly buffer MouseState {
+    vec2 position;
+    vec2 normalized;
+    vec2 click;
+    vec2 click_normalized;
+    vec2 drag;
+    vec2 wheel;
+    vec4 shadertoy;
+    uint buttons;
+    uint pressed;
+    uint released;
+    uint dragging;
+};
 
 layout(std430, push_constant) uniform PushConstant {
     vec3 pos;
@@ -55,6 +177,20 @@ layout(std430, push_constant) uniform PushConstant {
     uint frame;
     float time_delta;
     float record_time;
+    IndirectArgs indirect;
+    Params params;
+    Camera camera;
+    MouseState mouse_state;
+    float level;
+    float bass;
+    float mid;
+    float treble;
+    float bpm;
+    float beat_phase;
+    uint beat_count;
+    float beat_env;
+    float bar;
+    uint _pad;
 }
 pc;
 
@@ -71,6 +207,54 @@ pub const COMP_SHADER: &str = "#version 460
 
 layout(set = 0, binding = 0) uniform sampler gsamplers[];
 layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 0, binding = 2) uniform textureCube gcubes[];
+layout(set = 0, binding = 3) uniform texture3D gvolumes[];
+
+layout(std430, buffer_reference, buffer_reference_align = 4) buffer IndirectArgs {
+    uint vertex_count;
+    uint instance_count;
+    uint first_vertex;
+    uint first_instance;
+    uvec3 group_count;
+};
+
+layout(std430, buffer_reference, buffer_reference_align = 4) readonly buffer Params {
+    float values[];
+};
+
+layout(std430, buffer_reference, buffer_reference_align = 16) readonly buffer Camera {
+    mat4 view;
+    mat4 proj;
+    mat4 view_proj;
+    mat4 inv_view_proj;
+    vec3 position;
+    float fov;
+    vec3 right;
+    float aspect;
+    vec3 up;
+    float near;
+    vec3 forward;
+    float far;
+};
+
+const uint MOUSE_LEFT = 1;
+const uint MOUSE_RIGHT = 2;
+const uint MOUSE_MIDDLE = 4;
+
+// Pixels from the bottom-left corner
+layout(std430, buffer_reference, buffer_reference_align = 16) readonly buffer MouseState {
+    vec2 position;
+    vec2 normalized;
+    vec2 click;
+    vec2 click_normalized;
+    vec2 drag;
+    vec2 wheel;
+    vec4 shadertoy;
+    uint buttons;
+    uint pressed;
+    uint released;
+    uint dragging;
+};
 
 layout(std430, push_constant) uniform PushConstant {
     vec3 pos;
@@ -81,6 +265,20 @@ layout(std430, push_constant) uniform PushConstant {
     uint frame;
     float time_delta;
     float record_time;
+    IndirectArgs indirect;
+    Params params;
+    Camera camera;
+    MouseState mouse_state;
+    float level;
+    float bass;
+    float mid;
+    float treble;
+    float bpm;
+    float beat_phase;
+    uint beat_count;
+    float beat_env;
+    float bar;
+    uint _pad;
 }
 pc;
 
@@ -102,9 +300,16 @@ const uint GENERIC_TEX2 = 2;
 const uint DITHER_TEX = 3;
 const uint NOISE_TEX = 4;
 const uint BLUE_TEX = 5;
+const uint DEPTH_TEX = 6;
 
 const uint LINER_SAMPL = 0;
 const uint NEAREST_SAMPL = 1;
+const uint LINEAR_CLAMP_SAMPL = 2;
+const uint NEAREST_CLAMP_SAMPL = 3;
+const uint LINEAR_REPEAT_SAMPL = 4;
+const uint NEAREST_REPEAT_SAMPL = 5;
+const uint ANISO_SAMPL = 6;
+const uint SHADOW_SAMPL = 7;
 
 vec4 ASSERT_COL = vec4(0.);
 void assert(bool cond, int v) {
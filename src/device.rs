@@ -14,17 +14,22 @@ use ash::{
     vk::{self, DeviceMemory, Handle},
 };
 
-use crate::{align_to, ManagedImage, COLOR_SUBRESOURCE_MASK};
+use crate::{
+    align_to, format_aspect, texture_loader::mip_extent, ManagedImage, COLOR_SUBRESOURCE_MASK,
+};
 
 pub struct Device {
     pub physical_device: vk::PhysicalDevice,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
     pub device_properties: vk::PhysicalDeviceProperties,
+    /// Core features enabled on the device.
+    pub features: vk::PhysicalDeviceFeatures,
     pub descriptor_indexing_props: vk::PhysicalDeviceDescriptorIndexingProperties<'static>,
     pub command_pool: vk::CommandPool,
     pub main_queue_family_idx: u32,
     pub transfer_queue_family_idx: u32,
     pub allocator: Arc<Mutex<GpuAllocator<DeviceMemory>>>,
+    pub instance: ash::Instance,
     pub device: ash::Device,
     pub dynamic_rendering: khr::dynamic_rendering::Device,
     pub(crate) dbg_utils: ext::debug_utils::Device,
@@ -51,19 +56,33 @@ impl Device {
     }
 
     pub fn create_2d_view(&self, image: &vk::Image, format: vk::Format) -> VkResult<vk::ImageView> {
+        self.create_view(image, format, vk::ImageViewType::TYPE_2D)
+    }
+
+    pub fn create_view(
+        &self,
+        image: &vk::Image,
+        format: vk::Format,
+        view_type: vk::ImageViewType,
+    ) -> VkResult<vk::ImageView> {
+        let layer_count = match view_type {
+            vk::ImageViewType::CUBE => 6,
+            vk::ImageViewType::TYPE_2D | vk::ImageViewType::TYPE_3D => 1,
+            _ => vk::REMAINING_ARRAY_LAYERS,
+        };
         let view = unsafe {
             self.create_image_view(
                 &vk::ImageViewCreateInfo::default()
-                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .view_type(view_type)
                     .image(*image)
                     .format(format)
                     .subresource_range(
                         vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .aspect_mask(format_aspect(format))
                             .base_mip_level(0)
-                            .level_count(1)
+                            .level_count(vk::REMAINING_MIP_LEVELS)
                             .base_array_layer(0)
-                            .layer_count(1),
+                            .layer_count(layer_count),
                     ),
                 None,
             )?
@@ -71,6 +90,27 @@ impl Device {
         Ok(view)
     }
 
+    pub fn supported_sample_counts(&self) -> vk::SampleCountFlags {
+        let limits = &self.device_properties.limits;
+        limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+    }
+
+    pub fn format_properties(&self, format: vk::Format) -> vk::FormatProperties {
+        unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        }
+    }
+
+    pub fn supports_linear_blit(&self, format: vk::Format) -> bool {
+        let required = vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        self.format_properties(format)
+            .optimal_tiling_features
+            .contains(required)
+    }
+
     pub fn one_time_submit(
         &self,
         queue: &vk::Queue,
@@ -219,6 +259,86 @@ impl Device {
         unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
     }
 
+    /// Fills mip levels past the first one by successive blits. Expects every level in
+    /// `TRANSFER_DST_OPTIMAL` and leaves them in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn generate_mipmaps(
+        &self,
+        command_buffer: &vk::CommandBuffer,
+        image: &vk::Image,
+        extent: vk::Extent3D,
+        mip_levels: u32,
+        layers: u32,
+    ) {
+        let mut barrier = vk::ImageMemoryBarrier2::default()
+            .image(*image)
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .subresource_range(vk::ImageSubresourceRange {
+                level_count: 1,
+                ..COLOR_SUBRESOURCE_MASK
+            });
+        let to_read_only = |barrier: vk::ImageMemoryBarrier2<'static>| {
+            barrier
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        };
+        let offset = |extent: vk::Extent3D| vk::Offset3D {
+            x: extent.width as _,
+            y: extent.height as _,
+            z: extent.depth as _,
+        };
+
+        for level in 1..mip_levels {
+            barrier.subresource_range.base_mip_level = level - 1;
+            let dependency_info =
+                vk::DependencyInfo::default().image_memory_barriers(std::slice::from_ref(&barrier));
+            unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
+
+            let subresource = |mip_level| vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level,
+                base_array_layer: 0,
+                layer_count: layers,
+            };
+            let region = vk::ImageBlit2::default()
+                .src_offsets([
+                    vk::Offset3D::default(),
+                    offset(mip_extent(extent, level - 1)),
+                ])
+                .dst_offsets([vk::Offset3D::default(), offset(mip_extent(extent, level))])
+                .src_subresource(subresource(level - 1))
+                .dst_subresource(subresource(level));
+            let blit_info = vk::BlitImageInfo2::default()
+                .src_image(*image)
+                .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .dst_image(*image)
+                .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .regions(std::slice::from_ref(&region))
+                .filter(vk::Filter::LINEAR);
+            unsafe { self.cmd_blit_image2(*command_buffer, &blit_info) };
+
+            let read_only = to_read_only(
+                barrier
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                    .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+            );
+            let dependency_info = vk::DependencyInfo::default()
+                .image_memory_barriers(std::slice::from_ref(&read_only));
+            unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
+        }
+
+        barrier.subresource_range.base_mip_level = mip_levels - 1;
+        let last = to_read_only(barrier);
+        let dependency_info =
+            vk::DependencyInfo::default().image_memory_barriers(std::slice::from_ref(&last));
+        unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
+    }
+
     pub fn capture_image_data(
         self: &Arc<Self>,
         queue: &vk::Queue,
@@ -304,6 +424,37 @@ impl Device {
         })
     }
 
+    pub fn create_device_buffer(
+        self: &Arc<Self>,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<DeviceBuffer> {
+        let buffer = unsafe {
+            self.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(size)
+                    .usage(usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+                None,
+            )?
+        };
+        let mem_requirements = unsafe { self.get_buffer_memory_requirements(buffer) };
+
+        let memory = self.alloc_memory(mem_requirements, UsageFlags::FAST_DEVICE_ACCESS)?;
+        unsafe { self.bind_buffer_memory(buffer, *memory.memory(), memory.offset()) }?;
+
+        let address = unsafe {
+            self.get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer))
+        };
+
+        Ok(DeviceBuffer {
+            address,
+            size,
+            buffer,
+            memory: ManuallyDrop::new(memory),
+            device: self.clone(),
+        })
+    }
+
     pub fn create_host_buffer_typed<T>(
         self: Arc<Self>,
         usage: vk::BufferUsageFlags,
@@ -427,6 +578,24 @@ impl Drop for HostBuffer {
     }
 }
 
+pub struct DeviceBuffer {
+    pub address: u64,
+    pub size: u64,
+    pub buffer: vk::Buffer,
+    pub memory: ManuallyDrop<MemoryBlock<DeviceMemory>>,
+    device: Arc<Device>,
+}
+
+impl Drop for DeviceBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.buffer, None);
+            let memory = ManuallyDrop::take(&mut self.memory);
+            self.device.dealloc_memory(memory);
+        }
+    }
+}
+
 pub struct HostBufferTyped<T: 'static> {
     pub address: u64,
     pub buffer: vk::Buffer,
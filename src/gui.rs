@@ -0,0 +1,605 @@
+use std::sync::Arc;
+
+use ahash::AHashMap;
+use anyhow::{bail, Result};
+use ash::vk;
+use egui::{
+    epaint::{ImageDelta, Primitive},
+    ClippedPrimitive, Color32, ImageData, TextureId, TexturesDelta,
+};
+use winit::{
+    event::{ElementState, KeyEvent, WindowEvent},
+    window::Window,
+};
+
+use crate::{
+    param_constant_name, BlendMode, Device, FrameGuard, Params, PushConstant, ShaderCompiler,
+    TextureArena, TextureData, TransferQueue, KEY_BINDINGS,
+};
+
+const GUI_VERT: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+
+// egui vertices, 5 words each: position, uv and a packed color
+layout(std430, buffer_reference, buffer_reference_align = 4) readonly buffer Vertices {
+    uint data[];
+};
+
+layout(push_constant) uniform GuiConstant {
+    Vertices vertices;
+    vec2 screen_size;
+    uint texture_id;
+    uint srgb_target;
+} pc;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_color;
+
+void main() {
+    uint base = uint(gl_VertexIndex) * 5;
+    vec2 pos = uintBitsToFloat(uvec2(pc.vertices.data[base], pc.vertices.data[base + 1]));
+    out_uv = uintBitsToFloat(uvec2(pc.vertices.data[base + 2], pc.vertices.data[base + 3]));
+    out_color = unpackUnorm4x8(pc.vertices.data[base + 4]);
+    gl_Position = vec4(pos / pc.screen_size * 2.0 - 1.0, 0.0, 1.0);
+}
+";
+
+const GUI_FRAG: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_nonuniform_qualifier : require
+
+layout(std430, buffer_reference, buffer_reference_align = 4) readonly buffer Vertices {
+    uint data[];
+};
+
+layout(push_constant) uniform GuiConstant {
+    Vertices vertices;
+    vec2 screen_size;
+    uint texture_id;
+    uint srgb_target;
+} pc;
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 1) in vec4 in_color;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+
+const uint LINEAR_CLAMP_SAMPL = 2;
+
+vec3 srgb_to_linear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+void main() {
+    vec4 color = in_color * textureLod(
+        sampler2D(gtextures[pc.texture_id], gsamplers[LINEAR_CLAMP_SAMPL]), in_uv, 0.0);
+    // egui blends in gamma space, an sRGB target would encode the colors twice
+    if (pc.srgb_target != 0) {
+        color.rgb = srgb_to_linear(color.rgb);
+    }
+    out_color = color;
+}
+";
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct GuiConstant {
+    vertices: u64,
+    screen_size: [f32; 2],
+    texture_id: u32,
+    srgb_target: u32,
+}
+
+/// egui texture with a copy of its texels, egui patches glyphs into the font atlas.
+struct GuiTexture {
+    slot: u32,
+    size: [usize; 2],
+    pixels: Vec<Color32>,
+}
+
+impl GuiTexture {
+    fn texture_data(&self) -> TextureData {
+        let extent = vk::Extent3D {
+            width: self.size[0] as _,
+            height: self.size[1] as _,
+            depth: 1,
+        };
+        TextureData::from_levels(
+            vk::Format::R8G8B8A8_UNORM,
+            extent,
+            [bytemuck::cast_slice(&self.pixels)],
+        )
+    }
+}
+
+/// Shapes of one overlay frame, ready to be painted.
+pub struct GuiFrame {
+    primitives: Vec<ClippedPrimitive>,
+    textures_delta: TexturesDelta,
+    pixels_per_point: f32,
+}
+
+/// egui overlay drawn over the swapchain image, its textures live in the texture arena.
+pub struct Gui {
+    pub visible: bool,
+    ctx: egui::Context,
+    state: egui_winit::State,
+    textures: AHashMap<TextureId, GuiTexture>,
+    srgb_target: bool,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    device: Arc<Device>,
+}
+
+impl Drop for Gui {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+impl Gui {
+    pub fn new(
+        window: &Window,
+        device: &Arc<Device>,
+        shader_compiler: &ShaderCompiler,
+        texture_arena: &TextureArena,
+        format: vk::Format,
+    ) -> Result<Self> {
+        let ctx = egui::Context::default();
+        let state = egui_winit::State::new(
+            ctx.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            window.theme(),
+            None,
+        );
+
+        let vs_bytes =
+            shader_compiler.compile_source(GUI_VERT, shaderc::ShaderKind::Vertex, "gui.vert")?;
+        let fs_bytes =
+            shader_compiler.compile_source(GUI_FRAG, shaderc::ShaderKind::Fragment, "gui.frag")?;
+
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<GuiConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(std::slice::from_ref(&texture_arena.images_set_layout))
+                    .push_constant_ranges(std::slice::from_ref(&push_constant_range)),
+                None,
+            )?
+        };
+
+        let mut vs_module = vk::ShaderModuleCreateInfo::default().code(vs_bytes.as_binary());
+        let mut fs_module = vk::ShaderModuleCreateInfo::default().code(fs_bytes.as_binary());
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .name(c"main")
+                .push_next(&mut vs_module),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .name(c"main")
+                .push_next(&mut fs_module),
+        ];
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .line_width(1.);
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let color_blend_attachments = [BlendMode::Premultiplied.attachment_state()];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+        let color_attachment_formats = [format];
+        let mut dyn_render = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&color_attachment_formats);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(layout)
+            .push_next(&mut dyn_render);
+        let pipeline = unsafe {
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        };
+        let pipeline = match pipeline {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            visible: false,
+            ctx,
+            state,
+            textures: AHashMap::new(),
+            srgb_target: matches!(
+                format,
+                vk::Format::B8G8R8A8_SRGB
+                    | vk::Format::R8G8B8A8_SRGB
+                    | vk::Format::A8B8G8R8_SRGB_PACK32
+            ),
+            layout,
+            pipeline,
+            device: device.clone(),
+        })
+    }
+
+    /// Feeds a window event to egui. True when the overlay is visible and took the event,
+    /// the application should ignore it then.
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        let response = self.state.on_window_event(window, event);
+        // Releases always pass, buttons pressed outside of the overlay don't get stuck
+        let released = matches!(
+            event,
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                ..
+            } | WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Released,
+                    ..
+                },
+                ..
+            }
+        );
+        self.visible && response.consumed && !released
+    }
+
+    pub fn run(&mut self, window: &Window, ui: impl FnMut(&egui::Context)) -> GuiFrame {
+        let input = self.state.take_egui_input(window);
+        let output = self.ctx.run(input, ui);
+        self.state
+            .handle_platform_output(window, output.platform_output);
+        GuiFrame {
+            primitives: self.ctx.tessellate(output.shapes, output.pixels_per_point),
+            textures_delta: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+        }
+    }
+
+    /// Records the overlay on top of `view`, which is expected in `COLOR_ATTACHMENT_OPTIMAL`.
+    pub fn paint(
+        &mut self,
+        frame: &mut FrameGuard,
+        texture_arena: &mut TextureArena,
+        transfer: &mut TransferQueue,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+        gui_frame: GuiFrame,
+    ) -> Result<()> {
+        for (id, delta) in &gui_frame.textures_delta.set {
+            self.set_texture(texture_arena, transfer, *id, delta)?;
+        }
+        self.draw(frame, texture_arena, view, extent, &gui_frame)?;
+        for id in &gui_frame.textures_delta.free {
+            if let Some(texture) = self.textures.remove(id) {
+                texture_arena.remove_texture(texture.slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_texture(
+        &mut self,
+        texture_arena: &mut TextureArena,
+        transfer: &mut TransferQueue,
+        id: TextureId,
+        delta: &ImageDelta,
+    ) -> Result<()> {
+        let size = delta.image.size();
+        let pixels: Vec<Color32> = match &delta.image {
+            ImageData::Color(image) => image.pixels.clone(),
+            ImageData::Font(image) => image.srgba_pixels(None).collect(),
+        };
+
+        match delta.pos {
+            Some([x, y]) => {
+                let Some(texture) = self.textures.get_mut(&id) else {
+                    bail!("egui updated the unknown texture {id:?}");
+                };
+                for (row, texels) in pixels.chunks(size[0]).enumerate() {
+                    let start = (y + row) * texture.size[0] + x;
+                    texture.pixels[start..start + size[0]].copy_from_slice(texels);
+                }
+                texture_arena.replace_texture(transfer, texture.slot, &texture.texture_data())?;
+            }
+            None => {
+                let mut texture = GuiTexture {
+                    slot: 0,
+                    size,
+                    pixels,
+                };
+                let data = texture.texture_data();
+                texture.slot = match self.textures.get(&id) {
+                    Some(old) => {
+                        texture_arena.replace_texture(transfer, old.slot, &data)?;
+                        old.slot
+                    }
+                    None => texture_arena.push_texture(transfer, &data)?,
+                };
+                self.textures.insert(id, texture);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        texture_arena: &TextureArena,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+        gui_frame: &GuiFrame,
+    ) -> Result<()> {
+        let meshes: Vec<_> = gui_frame
+            .primitives
+            .iter()
+            .filter_map(
+                |ClippedPrimitive {
+                     clip_rect,
+                     primitive,
+                 }| match primitive {
+                    Primitive::Mesh(mesh) if !mesh.indices.is_empty() => Some((clip_rect, mesh)),
+                    _ => None,
+                },
+            )
+            .collect();
+        let vertex_count: usize = meshes.iter().map(|(_, mesh)| mesh.vertices.len()).sum();
+        let index_count: usize = meshes.iter().map(|(_, mesh)| mesh.indices.len()).sum();
+        if index_count == 0 {
+            return Ok(());
+        }
+
+        let mut vertices = self.device.create_host_buffer(
+            (vertex_count * size_of::<egui::epaint::Vertex>()) as _,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu_alloc::UsageFlags::UPLOAD,
+        )?;
+        let mut indices = self.device.create_host_buffer(
+            (index_count * size_of::<u32>()) as _,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            gpu_alloc::UsageFlags::UPLOAD,
+        )?;
+
+        frame.begin_overlay_rendering(view);
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &self.pipeline);
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            self.layout,
+            &[texture_arena.images_set],
+        );
+        frame.bind_index_buffer(indices.buffer, 0);
+
+        let ppp = gui_frame.pixels_per_point;
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let (mut first_vertex, mut first_index) = (0, 0);
+        for (clip_rect, mesh) in meshes {
+            let vertex_bytes: &[u8] = bytemuck::cast_slice(&mesh.vertices);
+            let index_bytes: &[u8] = bytemuck::cast_slice(&mesh.indices);
+            let vertex_start = first_vertex * size_of::<egui::epaint::Vertex>();
+            let index_start = first_index * size_of::<u32>();
+            vertices[vertex_start..vertex_start + vertex_bytes.len()].copy_from_slice(vertex_bytes);
+            indices[index_start..index_start + index_bytes.len()].copy_from_slice(index_bytes);
+            let (vertex_offset, index_offset) = (first_vertex, first_index);
+            first_vertex += mesh.vertices.len();
+            first_index += mesh.indices.len();
+
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            let min_x = (clip_rect.min.x * ppp).round().clamp(0., width);
+            let min_y = (clip_rect.min.y * ppp).round().clamp(0., height);
+            let max_x = (clip_rect.max.x * ppp).round().clamp(min_x, width);
+            let max_y = (clip_rect.max.y * ppp).round().clamp(min_y, height);
+            if max_x == min_x || max_y == min_y {
+                continue;
+            }
+            frame.set_scissors(&[vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: min_x as _,
+                    y: min_y as _,
+                },
+                extent: vk::Extent2D {
+                    width: (max_x - min_x) as _,
+                    height: (max_y - min_y) as _,
+                },
+            }]);
+            let constant = GuiConstant {
+                vertices: vertices.address,
+                screen_size: [width / ppp, height / ppp],
+                texture_id: texture.slot,
+                srgb_target: self.srgb_target as u32,
+            };
+            frame.push_constant(
+                self.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                &[constant],
+            );
+            frame.draw_indexed(
+                mesh.indices.len() as _,
+                index_offset as _,
+                vertex_offset as _,
+                1,
+                0,
+            );
+        }
+        frame.end_rendering();
+
+        frame.defer_drop(vertices);
+        frame.defer_drop(indices);
+        Ok(())
+    }
+}
+
+/// Changes to the timeline made in the overlay.
+#[derive(Debug, Default)]
+pub struct TimelineEdit {
+    pub seek: Option<f32>,
+    pub pause: Option<bool>,
+}
+
+/// Application state shown in the overlay, the parameters are edited in place.
+pub struct Overlay<'a> {
+    pub push_constant: &'a PushConstant,
+    pub params: &'a mut Params,
+    pub pause: bool,
+    /// End of the scrub bar in seconds.
+    pub duration: f32,
+    /// Constant name and slot of the textures from the project folder.
+    pub textures: Vec<(String, u32)>,
+    /// Error of the last shader reload.
+    pub shader_error: Option<&'a str>,
+    pub msaa_samples: u32,
+}
+
+impl Overlay<'_> {
+    pub fn show(&mut self, ctx: &egui::Context) -> TimelineEdit {
+        let mut edit = TimelineEdit::default();
+        egui::Window::new("pilka")
+            .default_width(340.)
+            .show(ctx, |ui| {
+                let pc = self.push_constant;
+                ui.label(format!(
+                    "{:.1} fps, {:.2} ms, frame {}",
+                    1. / pc.time_delta,
+                    pc.time_delta * 1000.,
+                    pc.frame
+                ));
+                match self.shader_error {
+                    None => ui.colored_label(Color32::LIGHT_GREEN, "Shaders compiled"),
+                    Some(err) => ui.colored_label(Color32::LIGHT_RED, err),
+                };
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let label = if self.pause { "Play" } else { "Pause" };
+                    if ui.button(label).clicked() {
+                        edit.pause = Some(!self.pause);
+                    }
+                    let mut time = pc.time;
+                    let slider =
+                        egui::Slider::new(&mut time, 0.0..=self.duration.max(pc.time)).suffix(" s");
+                    if ui.add(slider).changed() {
+                        edit.seek = Some(time);
+                    }
+                });
+
+                egui::CollapsingHeader::new("Parameters")
+                    .default_open(true)
+                    .show(ui, |ui| self.params_ui(ui));
+                egui::CollapsingHeader::new("Uniforms").show(ui, |ui| self.uniforms_ui(ui));
+                egui::CollapsingHeader::new("Textures").show(ui, |ui| {
+                    if self.textures.is_empty() {
+                        ui.label("The textures folder is empty");
+                    }
+                    egui::Grid::new("textures").num_columns(2).show(ui, |ui| {
+                        for (name, slot) in &self.textures {
+                            ui.monospace(name);
+                            ui.label(slot.to_string());
+                            ui.end_row();
+                        }
+                    });
+                });
+                egui::CollapsingHeader::new("Keys").show(ui, |ui| {
+                    egui::Grid::new("keys").num_columns(2).show(ui, |ui| {
+                        for (key, action) in KEY_BINDINGS {
+                            ui.monospace(*key);
+                            ui.label(*action);
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        edit
+    }
+
+    fn params_ui(&mut self, ui: &mut egui::Ui) {
+        if self.params.is_empty() {
+            ui.label("Declare parameters with `#pragma pilka param <name> <min>..<max>`");
+            return;
+        }
+        egui::Grid::new("params").num_columns(2).show(ui, |ui| {
+            for idx in 0..self.params.len() {
+                let desc = self.params.desc(idx);
+                let (name, (min, max)) = (desc.name.clone(), desc.range);
+                let selected = self.params.selected == Some(idx);
+                let label = ui
+                    .selectable_label(selected, &name)
+                    .on_hover_text(param_constant_name(&name));
+                if label.clicked() {
+                    self.params.selected = Some(idx);
+                }
+                let mut value = self.params.get(idx);
+                if ui.add(egui::Slider::new(&mut value, min..=max)).changed() {
+                    self.params.set(idx, value);
+                }
+                ui.end_row();
+            }
+        });
+    }
+
+    fn uniforms_ui(&self, ui: &mut egui::Ui) {
+        let pc = self.push_constant;
+        let rows = [
+            ("position", format!("{:.2?}", pc.pos)),
+            ("time", format!("{:.2} s", pc.time)),
+            ("resolution", format!("{} x {}", pc.wh[0], pc.wh[1])),
+            (
+                "mouse",
+                match pc.mouse_pressed {
+                    0 => format!("{:.2?}", pc.mouse),
+                    _ => format!("{:.2?} pressed", pc.mouse),
+                },
+            ),
+            ("record period", format!("{} s", pc.record_time)),
+            ("MSAA", format!("{} samples", self.msaa_samples)),
+            (
+                "audio",
+                format!(
+                    "level {:.2}, bass {:.2}, mid {:.2}, treble {:.2}",
+                    pc.level, pc.bass, pc.mid, pc.treble
+                ),
+            ),
+            (
+                "beat",
+                format!(
+                    "{:.1} bpm, beat {} + {:.2}, bar {:.2}",
+                    pc.bpm, pc.beat_count, pc.beat_phase, pc.bar
+                ),
+            ),
+        ];
+        egui::Grid::new("uniforms").num_columns(2).show(ui, |ui| {
+            for (name, value) in rows {
+                ui.label(name);
+                ui.monospace(value);
+                ui.end_row();
+            }
+        });
+    }
+}
@@ -1,4 +1,3 @@
-use super::PushConstant;
 use winit::{
     event::{ElementState, KeyEvent, RawKeyEvent},
     keyboard::{KeyCode, PhysicalKey},
@@ -12,6 +11,8 @@ pub struct Input {
     pub move_left: bool,
     pub move_up: bool,
     pub move_down: bool,
+    /// Mouse motion turns the camera, while the right button is held.
+    pub mouse_look: bool,
 }
 
 impl Input {
@@ -46,26 +47,4 @@ impl Input {
             }
         }
     }
-
-    pub fn process_position(&self, push_constant: &mut PushConstant) {
-        let dx = 0.01;
-        if self.move_left {
-            push_constant.pos[0] -= dx;
-        }
-        if self.move_right {
-            push_constant.pos[0] += dx;
-        }
-        if self.move_backward {
-            push_constant.pos[1] -= dx;
-        }
-        if self.move_forward {
-            push_constant.pos[1] += dx;
-        }
-        if self.move_down {
-            push_constant.pos[2] -= dx;
-        }
-        if self.move_up {
-            push_constant.pos[2] += dx;
-        }
-    }
 }
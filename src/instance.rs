@@ -194,7 +194,10 @@ impl Instance {
         let mut feature_dynamic_rendering =
             vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
 
-        let mut features = vk::PhysicalDeviceFeatures::default().shader_int64(true);
+        let supported_features = unsafe { self.get_physical_device_features(pdevice) };
+        let mut features = vk::PhysicalDeviceFeatures::default()
+            .shader_int64(true)
+            .sampler_anisotropy(supported_features.sampler_anisotropy == vk::TRUE);
         if cfg!(debug_assertions) {
             features.robust_buffer_access = 1;
         }
@@ -244,12 +247,14 @@ impl Instance {
         let device = Device {
             physical_device: pdevice,
             device_properties: device_properties.properties,
+            features,
             descriptor_indexing_props,
             main_queue_family_idx,
             transfer_queue_family_idx,
             command_pool,
             memory_properties,
             allocator: Arc::new(Mutex::new(allocator)),
+            instance: self.inner.clone(),
             device,
             dynamic_rendering,
             dbg_utils,
@@ -1,22 +1,40 @@
 #![allow(clippy::new_without_default)]
 #![allow(clippy::too_many_arguments)]
 
+mod audio;
+mod audio_analysis;
+mod audio_capture;
+mod beat;
+mod camera;
 pub mod default_shaders;
 mod device;
+mod gui;
 mod input;
 mod instance;
+mod midi;
+mod mouse;
+mod msaa;
+mod noise;
+mod osc;
+mod params;
 mod pipeline_arena;
+mod pragma;
 mod recorder;
 mod shader_compiler;
+mod sound;
 mod surface;
 mod swapchain;
 mod texture_arena;
+mod texture_loader;
+mod transfer;
+mod video;
 mod watcher;
 
 use std::{
     fs::File,
     io,
     mem::ManuallyDrop,
+    net::SocketAddr,
     ops::{Add, BitAnd, Not, Sub},
     path::Path,
     sync::Arc,
@@ -24,15 +42,37 @@ use std::{
 };
 
 pub use self::{
-    device::{Device, HostBufferTyped},
+    audio::{
+        is_audio_file, AudioClip, AudioPlayer, AudioSource, AudioTrack, FileSource,
+        AUDIO_EXTENSIONS,
+    },
+    audio_analysis::{Analyzer, AudioAnalysis, AUDIO_TEXTURE_WIDTH},
+    audio_capture::CaptureSource,
+    beat::{Beat, BeatTracker, BEATS_PER_BAR},
+    camera::{Camera, CameraMode, CameraUniform},
+    device::{Device, DeviceBuffer, HostBufferTyped},
+    gui::{Gui, GuiFrame, Overlay, TimelineEdit},
     input::Input,
     instance::Instance,
+    midi::{MidiBinding, MidiBindings, MidiControl, MidiController, MidiMessage, MidiPort},
+    mouse::{Mouse, MouseUniform, MOUSE_LEFT, MOUSE_MIDDLE, MOUSE_RIGHT},
+    msaa::MsaaTarget,
+    noise::{NoiseDesc, NoiseKind},
+    osc::{OscCommand, OscServer},
+    params::{param_constant_name, ParamDesc, Params, MAX_PARAMS},
     pipeline_arena::*,
-    recorder::{RecordEvent, Recorder},
-    shader_compiler::ShaderCompiler,
+    pragma::{declared_params, Pragma},
+    recorder::{RecordAudio, RecordEvent, Recorder, RECORD_FRAME_RATE},
+    shader_compiler::{is_shader_file, ShaderCompiler, SHADER_EXTENSIONS},
+    sound::{render_sound, SoundConstant, SOUND_DURATION, SOUND_SAMPLE_RATE},
     surface::Surface,
-    swapchain::Swapchain,
+    swapchain::{ColorAttachment, DepthAttachment, FrameGuard, Swapchain},
     texture_arena::*,
+    texture_loader::{
+        is_texture_file, load_texture, texture_constant_name, TextureData, TEXTURE_EXTENSIONS,
+    },
+    transfer::{TransferQueue, UploadId},
+    video::{is_video_file, VideoInfo, VideoTexture, VIDEO_EXTENSIONS},
     watcher::Watcher,
 };
 
@@ -44,8 +84,17 @@ use parking_lot::Mutex;
 
 pub const SHADER_DUMP_FOLDER: &str = "shader_dump";
 pub const SHADER_FOLDER: &str = "shaders";
+pub const TEXTURE_FOLDER: &str = "textures";
 pub const VIDEO_FOLDER: &str = "recordings";
 pub const SCREENSHOT_FOLDER: &str = "screenshots";
+/// Shader constant of the texture fed by `--audio-input`.
+pub const AUDIO_INPUT_TEXTURE: &str = "AUDIO_INPUT_TEX";
+/// Optional compute shader in the shader folder rendering the project's sound.
+pub const SOUND_SHADER: &str = "sound.comp";
+/// Shader constant of the texture fed by the sound shader.
+pub const SOUND_TEXTURE: &str = "SOUND_TEX";
+/// Controls bound to parameters with `--midi`, next to the shader folder.
+pub const MIDI_BINDINGS_FILE: &str = "midi_bindings.txt";
 
 pub const COLOR_SUBRESOURCE_MASK: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
     aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -55,6 +104,29 @@ pub const COLOR_SUBRESOURCE_MASK: vk::ImageSubresourceRange = vk::ImageSubresour
     layer_count: vk::REMAINING_ARRAY_LAYERS,
 };
 
+pub const DEPTH_SUBRESOURCE_MASK: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::DEPTH,
+    base_mip_level: 0,
+    level_count: vk::REMAINING_MIP_LEVELS,
+    base_array_layer: 0,
+    layer_count: vk::REMAINING_ARRAY_LAYERS,
+};
+
+pub fn format_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
 pub fn align_to<T>(value: T, alignment: T) -> T
 where
     T: Add<Output = T> + Copy + One + Not<Output = T> + BitAnd<Output = T> + Sub<Output = T>,
@@ -87,30 +159,71 @@ pub fn create_folder<P: AsRef<Path>>(name: P) -> io::Result<()> {
     Ok(())
 }
 
+/// Keys and their actions, printed on startup and listed in the overlay.
+pub const KEY_BINDINGS: &[(&str, &str)] = &[
+    ("F1", "Toggle the overlay"),
+    ("F2", "Toggle play/pause"),
+    ("F3", "Pause and step back one frame"),
+    ("F4", "Pause and step forward one frame"),
+    ("F5", "Restart playback at frame 0 (`Time` and `Pos` = 0)"),
+    ("Shift+F5", "Reset the camera"),
+    ("F7", "Cycle MSAA sample count"),
+    ("F8", "Tap tempo"),
+    (
+        "Shift+F8",
+        "Bind the next moved MIDI control to a parameter",
+    ),
+    ("F9", "Return tempo to beat detection"),
+    ("F10", "Save shaders"),
+    ("F11", "Take Screenshot"),
+    ("F12", "Start/Stop record video"),
+    ("Shift+F12", "Save the sound shader output as WAV"),
+    (
+        "Tab",
+        "Select the next parameter, `Shift+Tab` the previous one",
+    ),
+    (
+        "PageUp/PageDown",
+        "Change the selected parameter, finer with `Shift`",
+    ),
+    ("Home", "Reset the selected parameter"),
+    ("ESC", "Exit the application"),
+    ("WASD/Arrows", "Move the camera, `Q`/`E` down and up"),
+    ("Shift/Ctrl", "Move faster/slower"),
+    ("Right mouse", "Hold to look around"),
+    ("O", "Toggle orbit mode, movement keys zoom and pan"),
+];
+
 pub fn print_help() {
     println!();
-    println!("- `F1`:   Print help");
-    println!("- `F2`:   Toggle play/pause");
-    println!("- `F3`:   Pause and step back one frame");
-    println!("- `F4`:   Pause and step forward one frame");
-    println!("- `F5`:   Restart playback at frame 0 (`Time` and `Pos` = 0)");
-    println!("- `F6`:   Print parameters");
-    println!("- `F10`:  Save shaders");
-    println!("- `F11`:  Take Screenshot");
-    println!("- `F12`:  Start/Stop record video");
-    println!("- `ESC`:  Exit the application");
-    println!("- `Arrows`: Change `Pos`\n");
+    for (key, action) in KEY_BINDINGS {
+        println!("- {:<8} {action}", format!("`{key}`:"));
+    }
+    println!();
 }
 
 #[derive(Debug)]
 pub struct Args {
     pub inner_size: Option<(u32, u32)>,
     pub record_time: Option<Duration>,
+    pub msaa_samples: Option<u32>,
+    /// Capture device name pattern, `None` inside for the default device.
+    pub audio_input: Option<Option<String>>,
+    /// MIDI input port name pattern, `None` inside for the first port.
+    pub midi: Option<Option<String>>,
+    pub osc_port: Option<u16>,
+    /// Address the OSC state is sent to.
+    pub osc_feedback: Option<SocketAddr>,
 }
 
 pub fn parse_args() -> anyhow::Result<Args> {
     let mut inner_size = None;
     let mut record_time = None;
+    let mut msaa_samples = None;
+    let mut audio_input = None;
+    let mut midi = None;
+    let mut osc_port = None;
+    let mut osc_feedback = None;
     let args = std::env::args().skip(1).step_by(2);
     for (flag, value) in args.zip(std::env::args().skip(2).step_by(2)) {
         match flag.trim() {
@@ -131,6 +244,31 @@ pub fn parse_args() -> anyhow::Result<Args> {
                     .context("Failed to parse window size: Missing 'x' delimiter")?;
                 inner_size = Some((w.parse()?, h.parse()?));
             }
+            "--msaa" => {
+                let samples: u32 = value.parse()?;
+                if !samples.is_power_of_two() || samples > 8 {
+                    bail!("Failed to parse MSAA samples: Expected one of 1, 2, 4, 8");
+                }
+                msaa_samples = Some(samples);
+            }
+            "--audio-input" => {
+                audio_input = Some(Some(value).filter(|device| device != "default"));
+            }
+            "--midi" => {
+                midi = Some(Some(value).filter(|port| port != "default"));
+            }
+            "--osc" => {
+                let port = value
+                    .parse()
+                    .with_context(|| format!("Failed to parse OSC port `{value}`"))?;
+                osc_port = Some(port);
+            }
+            "--osc-feedback" => {
+                let address = value
+                    .parse()
+                    .with_context(|| format!("Failed to parse OSC feedback address `{value}`"))?;
+                osc_feedback = Some(address);
+            }
             _ => {}
         }
     }
@@ -138,6 +276,11 @@ pub fn parse_args() -> anyhow::Result<Args> {
     Ok(Args {
         record_time,
         inner_size,
+        msaa_samples,
+        audio_input,
+        midi,
+        osc_port,
+        osc_feedback,
     })
 }
 
@@ -152,8 +295,26 @@ pub struct PushConstant {
     pub frame: u32,
     pub time_delta: f32,
     pub record_time: f32,
+    pub indirect: u64,
+    pub params: u64,
+    pub camera: u64,
+    pub mouse_state: u64,
+    pub level: f32,
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+    pub bpm: f32,
+    pub beat_phase: f32,
+    pub beat_count: u32,
+    pub beat_env: f32,
+    pub bar: f32,
+    /// Spells out the tail padding of the 8-byte aligned block, the struct is pushed as raw bytes.
+    pub _pad: u32,
 }
 
+// Size of the std430 `PushConstant` block in the shaders
+const _: () = assert!(size_of::<PushConstant>() == 120);
+
 impl Default for PushConstant {
     fn default() -> Self {
         Self {
@@ -165,6 +326,55 @@ impl Default for PushConstant {
             frame: 0,
             time_delta: 1. / 60.,
             record_time: 10.,
+            indirect: 0,
+            params: 0,
+            camera: 0,
+            mouse_state: 0,
+            level: 0.,
+            bass: 0.,
+            mid: 0.,
+            treble: 0.,
+            bpm: 120.,
+            beat_phase: 0.,
+            beat_count: 0,
+            beat_env: 0.,
+            bar: 0.,
+            _pad: 0,
+        }
+    }
+}
+
+/// Arguments of the indirect draw and dispatch, shaders reach them through `pc.indirect`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IndirectArgs {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+    pub group_count_x: u32,
+    pub group_count_y: u32,
+    pub group_count_z: u32,
+}
+
+unsafe impl bytemuck::Zeroable for IndirectArgs {}
+unsafe impl bytemuck::Pod for IndirectArgs {}
+
+impl IndirectArgs {
+    pub const DRAW_OFFSET: u64 = std::mem::offset_of!(Self, vertex_count) as _;
+    pub const DISPATCH_OFFSET: u64 = std::mem::offset_of!(Self, group_count_x) as _;
+}
+
+impl Default for IndirectArgs {
+    fn default() -> Self {
+        Self {
+            vertex_count: 3,
+            instance_count: 1,
+            first_vertex: 0,
+            first_instance: 0,
+            group_count_x: 1,
+            group_count_y: 1,
+            group_count_z: 1,
         }
     }
 }
@@ -179,7 +389,9 @@ impl std::fmt::Display for PushConstant {
              time:\t\t{:#.2?}\n\
              time delta:\t{:#.3?}, fps: {:#.2?}\n\
              width, height:\t{:?}\nmouse:\t\t{:.2?}\n\
-             frame:\t\t{}\nrecord_period:\t{}\n",
+             frame:\t\t{}\nrecord_period:\t{}\n\
+             audio:\t\tlevel {:.2}, bass {:.2}, mid {:.2}, treble {:.2}\n\
+             beat:\t\t{:.1} bpm, beat {} + {:.2}, bar {:.2}\n",
             self.pos,
             time,
             time_delta,
@@ -187,7 +399,15 @@ impl std::fmt::Display for PushConstant {
             self.wh,
             self.mouse,
             self.frame,
-            self.record_time
+            self.record_time,
+            self.level,
+            self.bass,
+            self.mid,
+            self.treble,
+            self.bpm,
+            self.beat_count,
+            self.beat_phase,
+            self.bar,
         )
     }
 }
@@ -222,7 +442,17 @@ pub fn save_shaders<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
 
 #[derive(Debug)]
 pub enum UserEvent {
-    Glsl { path: std::path::PathBuf },
+    Glsl {
+        path: std::path::PathBuf,
+    },
+    Texture {
+        path: std::path::PathBuf,
+    },
+    /// Image decoded off the main thread after its file changed.
+    TextureDecoded {
+        path: std::path::PathBuf,
+        texture: anyhow::Result<TextureData>,
+    },
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -1,27 +1,37 @@
 use core::panic;
 use std::{
     io::Write,
+    net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
 
-use anyhow::{bail, Result};
+use ahash::{AHashMap, AHashSet};
+use anyhow::{bail, Context, Result};
 use ash::{khr, vk};
 use either::Either;
 use pilka::{
-    align_to, default_shaders, dispatch_optimal, parse_args, print_help, save_shaders, Args,
-    ComputeHandle, Device, FragmentOutputDesc, FragmentShaderDesc, Input, Instance, PipelineArena,
-    PushConstant, Recorder, RenderHandle, ShaderKind, ShaderSource, Surface, Swapchain,
-    TextureArena, UserEvent, VertexInputDesc, VertexShaderDesc, Watcher, COLOR_SUBRESOURCE_MASK,
-    PREV_FRAME_IMAGE_IDX, SCREENSIZED_IMAGE_INDICES, SHADER_FOLDER,
+    align_to, create_folder, declared_params, default_shaders, is_audio_file, is_shader_file,
+    is_texture_file, is_video_file, load_texture, param_constant_name, parse_args, print_help,
+    render_sound, save_shaders, texture_constant_name, Args, AudioClip, AudioTrack, BeatTracker,
+    Camera, CameraMode, CaptureSource, ColorAttachment, ComputeHandle, DepthAttachment, DepthDesc,
+    Device, DeviceBuffer, FileSource, FragmentOutputDesc, FragmentShaderDesc, FrameGuard, Gui,
+    GuiFrame, IndirectArgs, Input, Instance, MidiController, Mouse, MsaaTarget, OscCommand,
+    OscServer, Overlay, Params, PipelineArena, PushConstant, RecordAudio, Recorder, RenderHandle,
+    ShaderCompiler, ShaderKind, ShaderSource, SoundConstant, Surface, Swapchain, TextureArena,
+    TextureData, TimelineEdit, TransferQueue, UserEvent, VertexInputDesc, VertexShaderDesc,
+    VideoInfo, VideoTexture, Watcher, AUDIO_INPUT_TEXTURE, COLOR_SUBRESOURCE_MASK, DEPTH_IMAGE_IDX,
+    GENERIC_IMAGE1_IDX, GENERIC_IMAGE2_IDX, MIDI_BINDINGS_FILE, PREV_FRAME_IMAGE_IDX,
+    RECORD_FRAME_RATE, SCREENSIZED_IMAGE_INDICES, SHADER_FOLDER, SOUND_DURATION, SOUND_SHADER,
+    SOUND_TEXTURE, TEXTURE_FOLDER, VIDEO_FOLDER,
 };
 use winit::{
     application::ApplicationHandler,
     dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
     event::{ElementState, KeyEvent, MouseButton, StartCause, WindowEvent},
     event_loop::EventLoopProxy,
-    keyboard::{Key, NamedKey},
+    keyboard::{Key, KeyCode, ModifiersState, NamedKey, PhysicalKey},
     window::{Window, WindowAttributes},
 };
 
@@ -33,6 +43,7 @@ pub const MAX_FRAME_TIME: f64 = 15. * FIXED_TIME_STEP; // 0.25;
 struct AppInit {
     window: Window,
     input: Input,
+    modifiers: ModifiersState,
 
     pause: bool,
     timeline: Instant,
@@ -40,20 +51,42 @@ struct AppInit {
     frame_instant: Instant,
     frame_accumulated_time: f64,
 
+    transfer: TransferQueue,
     texture_arena: TextureArena,
+    user_textures: AHashMap<PathBuf, u32>,
+    videos: Vec<VideoTexture>,
+    audio: Option<AudioTrack>,
+    beat: BeatTracker,
+    sound_pipeline: Option<ComputeHandle>,
+    sound_slot: Option<u32>,
+    /// Last output of the sound shader.
+    sound: Option<AudioClip>,
+    params: Params,
+    camera: Camera,
+    mouse: Mouse,
+    midi: Option<MidiController>,
+    osc: Option<OscServer>,
+    msaa_target: MsaaTarget,
+    gui: Gui,
+    /// Error of the last shader reload, shown in the overlay.
+    shader_error: Option<String>,
+    /// OSC or MIDI learn named new parameters, shaders get their constants between frames.
+    params_added: bool,
 
     file_watcher: Watcher,
+    proxy: EventLoopProxy<UserEvent>,
     recorder: Recorder,
     video_recording: bool,
     record_time: Option<Duration>,
 
     push_constant: PushConstant,
+    indirect_buffer: DeviceBuffer,
+    depth_desc: DepthDesc,
     render_pipeline: RenderHandle,
     compute_pipeline: ComputeHandle,
     pipeline_arena: PipelineArena,
 
     queue: vk::Queue,
-    transfer_queue: vk::Queue,
 
     swapchain: Swapchain,
     surface: Surface,
@@ -67,10 +100,15 @@ impl AppInit {
         proxy: EventLoopProxy<UserEvent>,
         window_attributes: WindowAttributes,
         record_time: Option<Duration>,
+        msaa_samples: Option<u32>,
+        audio_input: Option<Option<String>>,
+        midi: Option<Option<String>>,
+        osc_port: Option<u16>,
+        osc_feedback: Option<SocketAddr>,
     ) -> Result<Self> {
         let window = event_loop.create_window(window_attributes)?;
-        let watcher = Watcher::new(proxy)?;
-        let mut recorder = Recorder::new();
+        let mut watcher = Watcher::new(proxy.clone())?;
+        let recorder = Recorder::new();
 
         let instance = Instance::new(Some(&window))?;
         let surface = instance.create_surface(&window)?;
@@ -82,15 +120,201 @@ impl AppInit {
 
         let mut pipeline_arena = PipelineArena::new(&device, watcher.clone())?;
 
+        let indirect_buffer = device.create_device_buffer(
+            size_of::<IndirectArgs>() as _,
+            vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+        device.name_object(indirect_buffer.buffer, "Indirect Buffer");
+        device.one_time_submit(&queue, |device, cbuff| unsafe {
+            let args = IndirectArgs::default();
+            device.cmd_update_buffer(cbuff, indirect_buffer.buffer, 0, bytemuck::bytes_of(&args));
+        })?;
+
+        let mut params = Params::new(&device)?;
+        let camera = Camera::new(&device)?;
+        let mouse = Mouse::new(&device)?;
+
         let extent = swapchain.extent();
         let video_recording = record_time.is_some();
         let push_constant = PushConstant {
             wh: [extent.width as f32, extent.height as f32],
             record_time: record_time.map(|t| t.as_secs_f32()).unwrap_or(10.),
+            indirect: indirect_buffer.address,
+            params: params.buffer.address,
+            camera: camera.buffer.address,
+            mouse_state: mouse.buffer.address,
             ..Default::default()
         };
 
-        let texture_arena = TextureArena::new(&device, &queue, swapchain.extent())?;
+        let depth_desc = DepthDesc::default();
+        let mut transfer = TransferQueue::new(&device, transfer_queue)?;
+        let mut texture_arena = TextureArena::new(
+            &device,
+            &queue,
+            &mut transfer,
+            swapchain.extent(),
+            depth_desc.format,
+        )?;
+
+        let mut user_textures = AHashMap::new();
+        let mut videos = vec![];
+        let mut audio = match audio_input {
+            Some(device) => {
+                let source = CaptureSource::new(device.as_deref())
+                    .context("Failed to open the audio input")?;
+                let idx =
+                    texture_arena.push_texture(&mut transfer, &AudioTrack::empty_texture())?;
+                pipeline_arena
+                    .shader_compiler
+                    .define(AUDIO_INPUT_TEXTURE, &idx.to_string());
+                Some(AudioTrack::new(Box::new(source), idx))
+            }
+            None => None,
+        };
+        for entry in std::fs::read_dir(TEXTURE_FOLDER)? {
+            let path = entry?.path().canonicalize()?;
+            let compiler = &mut pipeline_arena.shader_compiler;
+            let res = if is_video_file(&path) {
+                load_video(
+                    &mut texture_arena,
+                    compiler,
+                    &mut user_textures,
+                    &mut transfer,
+                    &path,
+                )
+                .map(|video| videos.push(video))
+            } else if is_audio_file(&path) {
+                if let Some(track) = &audio {
+                    log::warn!(
+                        "Ignoring {}, {} is already playing",
+                        path.display(),
+                        track.source().name()
+                    );
+                    continue;
+                }
+                load_audio(
+                    &mut texture_arena,
+                    compiler,
+                    &mut user_textures,
+                    &mut transfer,
+                    &path,
+                )
+                .map(|track| audio = Some(track))
+            } else if is_texture_file(&path) {
+                load_texture(&path).and_then(|texture| {
+                    load_user_texture(
+                        &mut texture_arena,
+                        compiler,
+                        &mut user_textures,
+                        &mut transfer,
+                        &path,
+                        &texture,
+                    )
+                    .map(drop)
+                })
+            } else {
+                continue;
+            };
+            if let Err(err) = res {
+                log::error!("{err:#}");
+            }
+        }
+        watcher.watch_file(TEXTURE_FOLDER)?;
+
+        let sound_path = Path::new(SHADER_FOLDER).join(SOUND_SHADER);
+        let (mut sound_pipeline, mut sound_slot, mut sound) = (None, None, None);
+        if sound_path.exists() {
+            let idx = texture_arena.push_texture(&mut transfer, &AudioTrack::empty_texture())?;
+            pipeline_arena
+                .shader_compiler
+                .define(SOUND_TEXTURE, &idx.to_string());
+            sound_slot = Some(idx);
+
+            let sound_range = vk::PushConstantRange::default()
+                .size(size_of::<SoundConstant>() as _)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE);
+            let res = pipeline_arena
+                .create_compute_pipeline(
+                    &sound_path,
+                    &[sound_range],
+                    &[texture_arena.images_set_layout],
+                )
+                .and_then(|handle| {
+                    sound_pipeline = Some(handle);
+                    render_sound(
+                        &device,
+                        &queue,
+                        pipeline_arena.get_pipeline(handle),
+                        texture_arena.images_set,
+                        sound_duration(record_time),
+                    )
+                });
+            match res {
+                Ok(clip) if audio.is_none() => {
+                    let source = FileSource::from_clip(&sound_path.canonicalize()?, clip.clone());
+                    audio = Some(AudioTrack::new(Box::new(source), idx));
+                    sound = Some(clip);
+                }
+                Ok(clip) => sound = Some(clip),
+                Err(err) => log::error!("{err:#}"),
+            }
+        }
+
+        for entry in std::fs::read_dir(SHADER_FOLDER)? {
+            let path = entry?.path();
+            if is_shader_file(&path) {
+                let compiler = &mut pipeline_arena.shader_compiler;
+                if let Err(err) = declare_params(&mut params, compiler, &path) {
+                    log::error!("{err:#}");
+                }
+            }
+        }
+
+        let midi = match midi {
+            Some(port) => {
+                let midi = MidiController::new(port.as_deref(), Path::new(MIDI_BINDINGS_FILE))
+                    .context("Failed to open the MIDI input")?;
+                for binding in midi.bindings.iter() {
+                    register_param(
+                        &mut params,
+                        &mut pipeline_arena.shader_compiler,
+                        &binding.name,
+                        binding.map(0.),
+                    )?;
+                }
+                Some(midi)
+            }
+            None => None,
+        };
+
+        let osc = match osc_port {
+            Some(port) => Some(OscServer::new(port, osc_feedback)?),
+            None => {
+                if osc_feedback.is_some() {
+                    log::warn!("`--osc-feedback` needs `--osc`, nothing will be sent");
+                }
+                None
+            }
+        };
+
+        let supported_samples = device.supported_sample_counts();
+        let samples = match msaa_samples.map(vk::SampleCountFlags::from_raw) {
+            Some(samples) if supported_samples.contains(samples) => samples,
+            Some(samples) => {
+                log::warn!("MSAA {samples:?} is not supported by the device, falling back to 1");
+                vk::SampleCountFlags::TYPE_1
+            }
+            None => vk::SampleCountFlags::TYPE_1,
+        };
+        let msaa_target = MsaaTarget::new(
+            &device,
+            swapchain.extent(),
+            swapchain.format(),
+            depth_desc.format,
+            samples,
+        )?;
 
         let vertex_shader_desc = VertexShaderDesc {
             shader_path: "shaders/shader.vert".into(),
@@ -101,6 +325,8 @@ impl AppInit {
         };
         let fragment_output_desc = FragmentOutputDesc {
             surface_format: swapchain.format(),
+            multisample_state: samples,
+            depth: Some(depth_desc),
             ..Default::default()
         };
         let push_constant_range = vk::PushConstantRange::default()
@@ -125,16 +351,18 @@ impl AppInit {
             &[texture_arena.images_set_layout],
         )?;
 
-        if record_time.is_some() {
-            let mut image_dimensions = swapchain.image_dimensions;
-            image_dimensions.width = align_to(image_dimensions.width, 2);
-            image_dimensions.height = align_to(image_dimensions.height, 2);
-            recorder.start(image_dimensions);
-        }
+        let gui = Gui::new(
+            &window,
+            &device,
+            &pipeline_arena.shader_compiler,
+            &texture_arena,
+            swapchain.format(),
+        )?;
 
-        Ok(Self {
+        let mut app = Self {
             window,
             input: Input::default(),
+            modifiers: ModifiersState::default(),
 
             pause: false,
             timeline: Instant::now(),
@@ -142,44 +370,90 @@ impl AppInit {
             frame_instant: Instant::now(),
             frame_accumulated_time: 0.,
 
+            transfer,
             texture_arena,
+            user_textures,
+            videos,
+            audio,
+            beat: BeatTracker::new(),
+            sound_pipeline,
+            sound_slot,
+            sound,
+            params,
+            camera,
+            mouse,
+            midi,
+            osc,
+            msaa_target,
+            gui,
+            shader_error: None,
+            params_added: false,
 
             file_watcher: watcher,
+            proxy,
             video_recording,
             record_time,
             recorder,
 
             push_constant,
+            indirect_buffer,
+            depth_desc,
             render_pipeline,
             compute_pipeline,
             pipeline_arena,
 
             queue,
-            transfer_queue,
 
             surface,
             swapchain,
             device,
             instance,
-        })
+        };
+        app.apply_image_targets()?;
+        if app.video_recording {
+            app.start_recording();
+        }
+
+        Ok(app)
     }
 
     fn update(&mut self) {
-        self.input.process_position(&mut self.push_constant);
+        let (fast, slow) = (self.modifiers.shift_key(), self.modifiers.control_key());
+        self.camera
+            .update(&self.input, fast, slow, FIXED_TIME_STEP as f32);
+        self.push_constant.pos = self.camera.position.to_array();
     }
 
     fn reload_shaders(&mut self, path: PathBuf) -> Result<()> {
-        if let Some(frame) = self.swapchain.get_current_frame() {
-            let fences = std::slice::from_ref(&frame.present_finished);
-            unsafe { self.device.wait_for_fences(fences, true, u64::MAX)? };
+        let compiler = &mut self.pipeline_arena.shader_compiler;
+        if let Err(err) = declare_params(&mut self.params, compiler, &path) {
+            log::warn!("{err:#}");
         }
-
         let resolved = {
             let mapping = self.file_watcher.include_mapping.lock();
             mapping[&path].clone()
         };
+        self.reload_shader_sources(resolved)
+    }
+
+    fn reload_all_shaders(&mut self) -> Result<()> {
+        let sources = {
+            let mapping = self.file_watcher.include_mapping.lock();
+            mapping.values().flatten().cloned().collect()
+        };
+        self.reload_shader_sources(sources)
+    }
+
+    fn reload_shader_sources(&mut self, sources: AHashSet<ShaderSource>) -> Result<()> {
+        if let Some(frame) = self.swapchain.get_current_frame() {
+            let fences = std::slice::from_ref(&frame.present_finished);
+            unsafe { self.device.wait_for_fences(fences, true, u64::MAX)? };
+        }
+
+        let sound_path = Path::new(SHADER_FOLDER).join(SOUND_SHADER).canonicalize();
+        let sound_changed = sound_path.is_ok_and(|sound| sources.iter().any(|s| s.path == sound));
 
-        for ShaderSource { path, kind } in resolved {
+        for ShaderSource { path, kind } in sources {
             let handles = &self.pipeline_arena.path_mapping[&path];
             for handle in handles {
                 let compiler = &self.pipeline_arena.shader_compiler;
@@ -202,9 +476,426 @@ impl AppInit {
                 }
             }
         }
+
+        if sound_changed {
+            self.update_sound()?;
+        }
+        self.apply_image_targets()
+    }
+
+    /// Renders the sound shader again, its playback restarts unless other audio plays.
+    fn update_sound(&mut self) -> Result<()> {
+        let (Some(handle), Some(slot)) = (self.sound_pipeline, self.sound_slot) else {
+            return Ok(());
+        };
+        let clip = render_sound(
+            &self.device,
+            &self.queue,
+            self.pipeline_arena.get_pipeline(handle),
+            self.texture_arena.images_set,
+            sound_duration(self.record_time),
+        )?;
+        let path = Path::new(SHADER_FOLDER).join(SOUND_SHADER).canonicalize()?;
+        let other_audio = self
+            .audio
+            .as_ref()
+            .is_some_and(|track| track.source().path() != Some(path.as_path()));
+        if !other_audio {
+            // The old output stream has to stop before the new one starts
+            self.audio = None;
+            let source = FileSource::from_clip(&path, clip.clone());
+            self.audio = Some(AudioTrack::new(Box::new(source), slot));
+        }
+        self.sound = Some(clip);
+        Ok(())
+    }
+
+    fn start_recording(&mut self) {
+        // The timeline follows the captured frames from here on
+        if !self.pause {
+            self.backup_time = self.timeline.elapsed();
+        }
+        let mut image_dimensions = self.swapchain.image_dimensions;
+        image_dimensions.width = align_to(image_dimensions.width, 2);
+        image_dimensions.height = align_to(image_dimensions.height, 2);
+        let audio = self
+            .record_audio()
+            .map_err(|err| log::error!("Recording without audio: {err:#}"))
+            .ok()
+            .flatten();
+        self.recorder.start(image_dimensions, audio);
+    }
+
+    /// Audio track muxed into a recording that starts now, the sound shader output
+    /// goes through a WAV file.
+    fn record_audio(&self) -> Result<Option<RecordAudio>> {
+        let Some(track) = &self.audio else {
+            return Ok(None);
+        };
+        let sound_path = Path::new(SHADER_FOLDER).join(SOUND_SHADER).canonicalize();
+        let path = match track.source().path() {
+            None => {
+                log::warn!("{} is not recorded", track.source().name());
+                return Ok(None);
+            }
+            Some(path) if sound_path.as_deref().is_ok_and(|sound| sound == path) => {
+                let Some(clip) = &self.sound else {
+                    return Ok(None);
+                };
+                let wav = std::env::temp_dir().join("pilka-sound.wav");
+                clip.write_wav(&wav)?;
+                wav
+            }
+            Some(path) => path.to_path_buf(),
+        };
+        Ok(Some(RecordAudio {
+            path,
+            offset: self.push_constant.time,
+        }))
+    }
+
+    fn save_sound(&self) -> Result<()> {
+        let Some(clip) = &self.sound else {
+            bail!("There is no {SOUND_SHADER} in the {SHADER_FOLDER} folder");
+        };
+        create_folder(VIDEO_FOLDER)?;
+        let path = Path::new(VIDEO_FOLDER).join(format!(
+            "sound-{}.wav",
+            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        ));
+        clip.write_wav(&path)?;
+        println!("Saved: {}", path.display());
+        Ok(())
+    }
+
+    fn reload_texture(&mut self, path: PathBuf) -> Result<()> {
+        if !path.exists() {
+            return self.remove_texture(&path);
+        }
+        let path = path.canonicalize()?;
+        let is_new = !self.user_textures.contains_key(&path);
+        let compiler = &mut self.pipeline_arena.shader_compiler;
+        if is_video_file(&path) {
+            self.videos.retain(|video| video.path() != path);
+            let video = load_video(
+                &mut self.texture_arena,
+                compiler,
+                &mut self.user_textures,
+                &mut self.transfer,
+                &path,
+            )?;
+            self.videos.push(video);
+        } else if is_audio_file(&path) {
+            let playing = self.audio.as_ref().map(|track| track.source());
+            if let Some(source) = playing.filter(|source| source.path() != Some(path.as_path())) {
+                bail!(
+                    "Ignoring {}, {} is already playing",
+                    path.display(),
+                    source.name()
+                );
+            }
+            self.audio = None;
+            let track = load_audio(
+                &mut self.texture_arena,
+                compiler,
+                &mut self.user_textures,
+                &mut self.transfer,
+                &path,
+            )?;
+            self.audio = Some(track);
+        } else {
+            // Large images take a while to decode, frames keep coming meanwhile
+            let proxy = self.proxy.clone();
+            std::thread::spawn(move || {
+                let texture = load_texture(&path);
+                let _ = proxy.send_event(UserEvent::TextureDecoded { path, texture });
+            });
+            return Ok(());
+        }
+        // Shaders only see the constant of a new texture after recompilation
+        if is_new {
+            self.reload_all_shaders()?;
+        }
+        Ok(())
+    }
+
+    fn land_decoded_texture(&mut self, path: PathBuf, texture: Result<TextureData>) -> Result<()> {
+        // Removed while it was decoding
+        if !path.exists() {
+            return Ok(());
+        }
+        let texture = texture?;
+        let is_new = !self.user_textures.contains_key(&path);
+        load_user_texture(
+            &mut self.texture_arena,
+            &mut self.pipeline_arena.shader_compiler,
+            &mut self.user_textures,
+            &mut self.transfer,
+            &path,
+            &texture,
+        )?;
+        if is_new {
+            self.reload_all_shaders()?;
+        }
+        Ok(())
+    }
+
+    fn remove_texture(&mut self, path: &Path) -> Result<()> {
+        // Deleted files can't be canonicalized, their folder still can
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(());
+        };
+        let path = dir.canonicalize()?.join(name);
+        let Some(idx) = self.user_textures.remove(&path) else {
+            return Ok(());
+        };
+        self.videos.retain(|video| video.path() != path);
+        if self
+            .audio
+            .as_ref()
+            .is_some_and(|track| track.source().path() == Some(path.as_path()))
+        {
+            self.audio = None;
+            let pc = &mut self.push_constant;
+            (pc.level, pc.bass, pc.mid, pc.treble) = (0., 0., 0., 0.);
+        }
+        self.texture_arena.remove_texture(idx)?;
+        self.pipeline_arena
+            .shader_compiler
+            .undefine(&texture_constant_name(&path));
+        log::info!("Removed {}", path.display());
+        self.reload_all_shaders()
+    }
+
+    /// Uploads the frames of the videos that changed since the last redraw.
+    fn upload_video_frames(&mut self, frame: &mut FrameGuard) {
+        let time = self.push_constant.time;
+        let (device, texture_arena) = (&self.device, &self.texture_arena);
+        self.videos.retain_mut(|video| {
+            let res = video.frame_at(time).and_then(|texels| {
+                let Some(texels) = texels else {
+                    return Ok(());
+                };
+                let texture = video.texture_data(&texels);
+                upload_frame_texture(device, texture_arena, frame, video.slot, &texture)
+            });
+            if let Err(err) = &res {
+                log::error!("Stopping {}: {err:#}", video.path().display());
+            }
+            res.is_ok()
+        });
+    }
+
+    /// Applies the MIDI messages to the parameters, a learned binding gets its own one.
+    fn update_midi(&mut self) -> Result<()> {
+        let Some(midi) = &mut self.midi else {
+            return Ok(());
+        };
+        let target = self.params.selected.map(|idx| self.params.desc(idx));
+        if let Some(binding) = midi.poll(target) {
+            midi.bindings.save(Path::new(MIDI_BINDINGS_FILE))?;
+            let is_new = self.params.index(&binding.name).is_none();
+            register_param(
+                &mut self.params,
+                &mut self.pipeline_arena.shader_compiler,
+                &binding.name,
+                binding.map(0.),
+            )?;
+            println!(
+                "Bound {} to {}",
+                binding.control,
+                param_constant_name(&binding.name)
+            );
+            self.params_added |= is_new;
+        }
+
+        let Some(midi) = &mut self.midi else {
+            return Ok(());
+        };
+        for (name, value) in midi.values() {
+            if let Some(idx) = self.params.index(name) {
+                self.params.set(idx, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds this frame's overlay and applies its timeline changes,
+    /// recordings go without it.
+    fn run_gui(&mut self) -> Option<GuiFrame> {
+        if !self.gui.visible || self.video_recording {
+            return None;
+        }
+        let mut textures: Vec<_> = self
+            .user_textures
+            .iter()
+            .map(|(path, &slot)| (texture_constant_name(path), slot))
+            .collect();
+        if let Some(track) = self
+            .audio
+            .as_ref()
+            .filter(|track| track.source().path().is_none())
+        {
+            textures.push((AUDIO_INPUT_TEXTURE.into(), track.slot));
+        }
+        if let Some(slot) = self.sound_slot {
+            textures.push((SOUND_TEXTURE.into(), slot));
+        }
+        textures.sort_by_key(|&(_, slot)| slot);
+
+        let mut overlay = Overlay {
+            push_constant: &self.push_constant,
+            params: &mut self.params,
+            pause: self.pause,
+            duration: self
+                .record_time
+                .map_or(SOUND_DURATION, |time| time.as_secs_f32()),
+            textures,
+            shader_error: self.shader_error.as_deref(),
+            msaa_samples: self.msaa_target.samples.as_raw(),
+        };
+        let mut edit = TimelineEdit::default();
+        let gui_frame = self.gui.run(&self.window, |ctx| edit = overlay.show(ctx));
+        if let Some(pause) = edit.pause {
+            self.set_pause(pause);
+        }
+        if let Some(time) = edit.seek {
+            self.seek(Duration::from_secs_f32(time));
+        }
+        Some(gui_frame)
+    }
+
+    fn print_param(&self, idx: usize) {
+        let desc = self.params.desc(idx);
+        let (min, max) = desc.range;
+        println!("{}: {:.3} ({min}..{max})", desc.name, self.params.get(idx));
+    }
+
+    /// Applies the OSC commands received since the last frame and sends the state back.
+    fn update_osc(&mut self) -> Result<()> {
+        let Some(osc) = &self.osc else {
+            return Ok(());
+        };
+        let commands: Vec<_> = osc.poll().collect();
+        for command in commands {
+            match command {
+                OscCommand::Param { name, value } => {
+                    let is_new = self.params.index(&name).is_none();
+                    match register_param(
+                        &mut self.params,
+                        &mut self.pipeline_arena.shader_compiler,
+                        &name,
+                        value,
+                    ) {
+                        Ok(idx) => {
+                            self.params.set(idx, value);
+                            self.params_added |= is_new;
+                        }
+                        Err(err) => log::warn!("{err}"),
+                    }
+                }
+                OscCommand::Seek(time) => self.seek(Duration::from_secs_f32(time)),
+                OscCommand::Pause(pause) => self.set_pause(pause.unwrap_or(!self.pause)),
+            }
+        }
+        if let Some(osc) = &mut self.osc {
+            osc.send_feedback(self.push_constant.time, self.pause, self.params.iter())?;
+        }
         Ok(())
     }
 
+    fn set_pause(&mut self, pause: bool) {
+        if pause == self.pause {
+            return;
+        }
+        if pause {
+            self.backup_time = self.current_time();
+        } else {
+            self.timeline = Instant::now() - self.backup_time;
+        }
+        self.pause = pause;
+    }
+
+    /// Time on the timeline, it stands still while paused and follows the captured frames
+    /// while recording.
+    fn current_time(&self) -> Duration {
+        if self.pause || self.records_frames() {
+            self.backup_time
+        } else {
+            self.timeline.elapsed()
+        }
+    }
+
+    /// Captured frames are played back at a fixed rate, however long they took to render.
+    fn records_frames(&self) -> bool {
+        self.video_recording && self.recorder.ffmpeg_installed()
+    }
+
+    /// Moves the timeline to `time`, paused or not.
+    fn seek(&mut self, time: Duration) {
+        let now = Instant::now();
+        self.backup_time = time;
+        self.timeline = now.checked_sub(time).unwrap_or(now);
+    }
+
+    /// Analyses the audio track at the current time into its texture, the audio
+    /// and the beat uniforms.
+    fn update_audio(&mut self, frame: &mut FrameGuard) {
+        let time = self.push_constant.time;
+        let mut flux = None;
+        if let Some(track) = &mut self.audio {
+            let analysis = track.update(time, !self.pause, self.push_constant.time_delta);
+            let pc = &mut self.push_constant;
+            (pc.level, pc.bass, pc.mid, pc.treble) =
+                (analysis.level, analysis.bass, analysis.mid, analysis.treble);
+            flux = Some(analysis.flux);
+            let texture = analysis.texture_data();
+            let res = upload_frame_texture(
+                &self.device,
+                &self.texture_arena,
+                frame,
+                track.slot,
+                &texture,
+            );
+            if let Err(err) = res {
+                log::error!("Stopping {}: {err:#}", track.source().name());
+                self.audio = None;
+            }
+        }
+
+        let beat = self.beat.update(time, flux);
+        let pc = &mut self.push_constant;
+        (pc.bpm, pc.beat_phase, pc.beat_count, pc.beat_env, pc.bar) =
+            (beat.bpm, beat.phase, beat.count, beat.envelope, beat.bar);
+    }
+
+    /// Recreates the screen-sized images whose format got changed by a `target` pragma.
+    fn apply_image_targets(&mut self) -> Result<()> {
+        let mut formats = [vk::Format::R8G8B8A8_SRGB; 3];
+        let mut mips = [false; 3];
+        let render = self.pipeline_arena.get_pipeline(self.render_pipeline);
+        let compute = self.pipeline_arena.get_pipeline(self.compute_pipeline);
+        for &(image_idx, format, image_mips) in
+            render.image_targets.iter().chain(&compute.image_targets)
+        {
+            formats[image_idx] = format;
+            mips[image_idx] |= image_mips;
+        }
+
+        let indices: Vec<_> = [PREV_FRAME_IMAGE_IDX, GENERIC_IMAGE1_IDX, GENERIC_IMAGE2_IDX]
+            .into_iter()
+            .filter(|&i| {
+                let info = &self.texture_arena.image_infos[i];
+                info.format != formats[i] || (info.mip_levels > 1) != mips[i]
+            })
+            .collect();
+        for &i in &indices {
+            self.texture_arena.image_infos[i].format = formats[i];
+            self.texture_arena.set_screen_mips(i, mips[i]);
+        }
+        self.texture_arena.update_images(&indices)
+    }
+
     fn recreate_swapchain(&mut self) -> Result<()> {
         if let Some(frame) = self.swapchain.get_current_frame() {
             let fences = std::slice::from_ref(&frame.present_finished);
@@ -223,9 +914,42 @@ impl AppInit {
                 height: extent.height,
                 depth: 1,
             };
+            let mips = self.texture_arena.image_infos[i].mip_levels > 1;
+            self.texture_arena.set_screen_mips(i, mips);
         }
         self.texture_arena
             .update_images(&SCREENSIZED_IMAGE_INDICES)?;
+        if self.msaa_target.is_enabled() {
+            // Other frames in flight may still render into the MSAA images
+            unsafe { self.device.device_wait_idle()? };
+        }
+        self.msaa_target.resize(extent)?;
+
+        Ok(())
+    }
+
+    fn cycle_msaa(&mut self) -> Result<()> {
+        // Every frame in flight may still render into the MSAA images
+        unsafe { self.device.device_wait_idle()? };
+
+        let supported = self.device.supported_sample_counts();
+        let mut samples = self.msaa_target.samples;
+        samples = loop {
+            samples = vk::SampleCountFlags::from_raw(samples.as_raw() << 1);
+            if samples.as_raw() > vk::SampleCountFlags::TYPE_8.as_raw() {
+                break vk::SampleCountFlags::TYPE_1;
+            }
+            if supported.contains(samples) {
+                break samples;
+            }
+        };
+
+        self.msaa_target.set_samples(samples)?;
+        for pipeline in self.pipeline_arena.render.pipelines.values_mut() {
+            pipeline.set_samples(samples)?;
+            pipeline.link()?;
+        }
+        println!("MSAA: {} samples", samples.as_raw());
 
         Ok(())
     }
@@ -237,11 +961,7 @@ impl ApplicationHandler<UserEvent> for AppInit {
         event_loop: &winit::event_loop::ActiveEventLoop,
         cause: winit::event::StartCause,
     ) {
-        self.push_constant.time = if !self.pause {
-            self.timeline.elapsed().as_secs_f32()
-        } else {
-            self.backup_time.as_secs_f32()
-        };
+        self.push_constant.time = self.current_time().as_secs_f32();
         if let StartCause::WaitCancelled { .. } = cause {
             let new_instant = Instant::now();
             let frame_time = new_instant
@@ -249,7 +969,11 @@ impl ApplicationHandler<UserEvent> for AppInit {
                 .as_secs_f64()
                 .min(MAX_FRAME_TIME);
             self.frame_instant = new_instant;
-            self.push_constant.time_delta = frame_time as _;
+            self.push_constant.time_delta = if self.records_frames() {
+                1. / RECORD_FRAME_RATE as f32
+            } else {
+                frame_time as _
+            };
 
             self.frame_accumulated_time += frame_time;
             while self.frame_accumulated_time >= FIXED_TIME_STEP {
@@ -260,7 +984,7 @@ impl ApplicationHandler<UserEvent> for AppInit {
         }
 
         if let Some(limit) = self.record_time {
-            if self.timeline.elapsed() >= limit && self.recorder.is_active() {
+            if self.current_time() >= limit && self.recorder.is_active() {
                 self.recorder.finish();
                 event_loop.exit();
             }
@@ -273,8 +997,12 @@ impl ApplicationHandler<UserEvent> for AppInit {
         _device_id: winit::event::DeviceId,
         event: winit::event::DeviceEvent,
     ) {
-        if let winit::event::DeviceEvent::Key(key_event) = event {
-            self.input.update_device_input(key_event);
+        match event {
+            winit::event::DeviceEvent::Key(key_event) => self.input.update_device_input(key_event),
+            winit::event::DeviceEvent::MouseMotion { delta: (dx, dy) } if self.input.mouse_look => {
+                self.camera.look(dx as f32, dy as f32)
+            }
+            _ => {}
         }
     }
 
@@ -284,6 +1012,9 @@ impl ApplicationHandler<UserEvent> for AppInit {
         _window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
+        if self.gui.on_window_event(&self.window, &event) {
+            return;
+        }
         match event {
             WindowEvent::CloseRequested
             | WindowEvent::KeyboardInput {
@@ -308,38 +1039,55 @@ impl ApplicationHandler<UserEvent> for AppInit {
             } => {
                 let dt = Duration::from_secs_f32(1. / 60.);
                 match key {
-                    NamedKey::F1 => print_help(),
-                    NamedKey::F2 => {
-                        if !self.pause {
-                            self.backup_time = self.timeline.elapsed();
-                        } else {
-                            self.timeline = Instant::now() - self.backup_time;
-                        }
-                        self.pause = !self.pause;
-                    }
+                    NamedKey::F1 => self.gui.visible = !self.gui.visible,
+                    NamedKey::F2 => self.set_pause(!self.pause),
                     NamedKey::F3 => {
                         if !self.pause {
-                            self.backup_time = self.timeline.elapsed();
+                            self.backup_time = self.current_time();
                             self.pause = true;
                         }
                         self.backup_time = self.backup_time.saturating_sub(dt);
                     }
                     NamedKey::F4 => {
                         if !self.pause {
-                            self.backup_time = self.timeline.elapsed();
+                            self.backup_time = self.current_time();
                             self.pause = true;
                         }
                         self.backup_time += dt;
                     }
+                    NamedKey::F5 if self.modifiers.shift_key() => {
+                        self.camera.reset();
+                        self.push_constant.pos = self.camera.position.to_array();
+                    }
                     NamedKey::F5 => {
-                        self.push_constant.pos = [0.; 3];
+                        self.camera.reset();
+                        self.push_constant.pos = self.camera.position.to_array();
                         self.push_constant.time = 0.;
                         self.push_constant.frame = 0;
                         self.timeline = Instant::now();
                         self.backup_time = self.timeline.elapsed();
                     }
-                    NamedKey::F6 => {
-                        println!("{}", self.push_constant);
+                    NamedKey::F7 => {
+                        let _ = self.cycle_msaa().map_err(|err| log::error!("{err}"));
+                    }
+                    NamedKey::F8 if self.modifiers.shift_key() => match &mut self.midi {
+                        Some(midi) => {
+                            midi.learning = !midi.learning;
+                            if midi.learning {
+                                println!("MIDI learn: move a control on {}", midi.port_name());
+                            } else {
+                                println!("MIDI learn cancelled");
+                            }
+                        }
+                        None => log::warn!("MIDI learn needs a port opened with `--midi`"),
+                    },
+                    NamedKey::F8 => {
+                        self.beat.tap();
+                        println!("Tempo: {:.1} BPM", self.beat.beat().bpm);
+                    }
+                    NamedKey::F9 => {
+                        self.beat.unlock();
+                        println!("Tempo follows the beat detection");
                     }
                     NamedKey::F10 => {
                         let _ = save_shaders(SHADER_FOLDER).map_err(|err| log::error!("{err}"));
@@ -355,41 +1103,79 @@ impl ApplicationHandler<UserEvent> for AppInit {
                             )
                             .map_err(|err| log::error!("{err}"));
                     }
+                    NamedKey::F12 if self.modifiers.shift_key() => {
+                        let _ = self.save_sound().map_err(|err| log::error!("{err:#}"));
+                    }
                     NamedKey::F12 => {
                         if !self.video_recording {
-                            let mut image_dimensions = self.swapchain.image_dimensions;
-                            image_dimensions.width = align_to(image_dimensions.width, 2);
-                            image_dimensions.height = align_to(image_dimensions.height, 2);
-                            self.recorder.start(image_dimensions);
+                            self.start_recording();
                         } else {
                             self.recorder.finish();
                         }
                         self.video_recording = !self.video_recording;
                     }
+                    NamedKey::Tab => {
+                        let step = if self.modifiers.shift_key() { -1 } else { 1 };
+                        match self.params.select(step) {
+                            Some(idx) => self.print_param(idx),
+                            None => println!("No parameters, declare them with a `param` pragma"),
+                        }
+                    }
+                    NamedKey::PageUp | NamedKey::PageDown => {
+                        let mut steps = if key == NamedKey::PageUp { 1. } else { -1. };
+                        if self.modifiers.shift_key() {
+                            steps *= 0.1;
+                        }
+                        if let Some(idx) = self.params.nudge(steps) {
+                            self.print_param(idx);
+                        }
+                    }
+                    NamedKey::Home => {
+                        if let Some(idx) = self.params.reset() {
+                            self.print_param(idx);
+                        }
+                    }
                     _ => {}
                 }
             }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyO),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => match self.camera.toggle_orbit() {
+                CameraMode::Orbit => println!("Camera: orbit"),
+                CameraMode::Fly => println!("Camera: fly"),
+            },
             WindowEvent::KeyboardInput { event, .. } => {
                 self.input.update_window_input(&event);
             }
-
-            WindowEvent::MouseInput {
-                state,
-                button: MouseButton::Left,
-                ..
-            } => {
-                self.push_constant.mouse_pressed = (ElementState::Pressed == state) as u32;
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = state == ElementState::Pressed;
+                self.mouse.button(button, pressed);
+                match button {
+                    MouseButton::Left => self.push_constant.mouse_pressed = pressed as u32,
+                    MouseButton::Right => self.input.mouse_look = pressed,
+                    _ => {}
+                }
             }
+            WindowEvent::MouseWheel { delta, .. } => self.mouse.scroll(delta),
+            // Keeps updating while paused, stills can be inspected
             WindowEvent::CursorMoved {
                 position: PhysicalPosition { x, y },
                 ..
             } => {
-                if !self.pause {
-                    let PhysicalSize { width, height } = self.window.inner_size();
-                    let x = (x as f32 / width as f32 - 0.5) * 2.;
-                    let y = -(y as f32 / height as f32 - 0.5) * 2.;
-                    self.push_constant.mouse = [x, y];
-                }
+                let PhysicalSize { width, height } = self.window.inner_size();
+                self.mouse.cursor_moved(x as f32, height as f32 - y as f32);
+                let x = (x as f32 / width as f32 - 0.5) * 2.;
+                let y = -(y as f32 / height as f32 - 0.5) * 2.;
+                self.push_constant.mouse = [x, y];
             }
             WindowEvent::RedrawRequested => {
                 let mut frame = match self.swapchain.acquire_next_image() {
@@ -401,6 +1187,19 @@ impl ApplicationHandler<UserEvent> for AppInit {
                     }
                     Err(e) => panic!("error: {e}\n"),
                 };
+                self.texture_arena.record_depth_layout(&frame);
+                self.texture_arena.land_uploads(&frame, &mut self.transfer);
+                self.texture_arena.release_retired(&mut frame);
+                self.upload_video_frames(&mut frame);
+                self.update_audio(&mut frame);
+                let _ = self.update_osc().map_err(|err| log::error!("{err:#}"));
+                let _ = self.update_midi().map_err(|err| log::error!("{err:#}"));
+                let gui_frame = self.run_gui();
+                self.params.record_upload(&frame);
+                let [width, height] = self.push_constant.wh;
+                self.camera
+                    .record_upload(&frame, width.max(1.) / height.max(1.));
+                self.mouse.record_upload(&frame, self.push_constant.wh);
 
                 let stages = vk::ShaderStageFlags::VERTEX
                     | vk::ShaderStageFlags::FRAGMENT
@@ -413,30 +1212,46 @@ impl ApplicationHandler<UserEvent> for AppInit {
                     &[self.texture_arena.images_set],
                 );
                 frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
-                const SUBGROUP_SIZE: u32 = 16;
-                let extent = self.swapchain.extent();
-                frame.dispatch(
-                    dispatch_optimal(extent.width, SUBGROUP_SIZE),
-                    dispatch_optimal(extent.height, SUBGROUP_SIZE),
-                    1,
-                );
+                if pipeline.is_indirect() {
+                    frame.indirect_barrier(
+                        vk::PipelineStageFlags2::ALL_GRAPHICS
+                            | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    );
+                    frame.dispatch_indirect(
+                        self.indirect_buffer.buffer,
+                        IndirectArgs::DISPATCH_OFFSET,
+                    );
+                } else {
+                    let [x, y, z] = pipeline.workgroup_count(self.swapchain.extent());
+                    frame.dispatch(x, y, z);
+                }
 
                 unsafe {
                     let image_barrier = vk::ImageMemoryBarrier2::default()
                         .subresource_range(COLOR_SUBRESOURCE_MASK)
                         .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
                         .dst_stage_mask(vk::PipelineStageFlags2::ALL_GRAPHICS)
-                        .image(self.texture_arena.images[PREV_FRAME_IMAGE_IDX].image);
+                        .image(*self.texture_arena.image(PREV_FRAME_IMAGE_IDX));
                     self.device.cmd_pipeline_barrier2(
                         *frame.command_buffer(),
                         &vk::DependencyInfo::default()
                             .image_memory_barriers(std::slice::from_ref(&image_barrier)),
                     )
                 };
+                frame.indirect_barrier(vk::PipelineStageFlags2::COMPUTE_SHADER);
 
                 frame.begin_rendering(
-                    self.swapchain.get_current_image_view(),
-                    [0., 0.025, 0.025, 1.0],
+                    ColorAttachment {
+                        view: self.swapchain.get_current_image_view(),
+                        msaa: self.msaa_target.color(),
+                        clear_value: [0., 0.025, 0.025, 1.0],
+                    },
+                    Some(DepthAttachment {
+                        image: self.texture_arena.image(DEPTH_IMAGE_IDX),
+                        view: self.texture_arena.view(DEPTH_IMAGE_IDX),
+                        msaa: self.msaa_target.depth(),
+                        clear_value: self.depth_desc.clear_value,
+                    }),
                 );
                 let pipeline = self.pipeline_arena.get_pipeline(self.render_pipeline);
                 frame.push_constant(pipeline.layout, stages, &[self.push_constant]);
@@ -447,7 +1262,11 @@ impl ApplicationHandler<UserEvent> for AppInit {
                 );
                 frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
 
-                frame.draw(3, 0, 1, 0);
+                if pipeline.indirect {
+                    frame.draw_indirect(self.indirect_buffer.buffer, IndirectArgs::DRAW_OFFSET, 1);
+                } else {
+                    frame.draw(pipeline.vertex_count, 0, 1, 0);
+                }
                 frame.end_rendering();
 
                 self.device.blit_image(
@@ -455,10 +1274,29 @@ impl ApplicationHandler<UserEvent> for AppInit {
                     self.swapchain.get_current_image(),
                     self.swapchain.extent(),
                     vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                    &self.texture_arena.images[PREV_FRAME_IMAGE_IDX].image,
+                    self.texture_arena.image(PREV_FRAME_IMAGE_IDX),
                     self.swapchain.extent(),
                     vk::ImageLayout::UNDEFINED,
                 );
+                self.texture_arena.record_screen_mips(
+                    frame.command_buffer(),
+                    PREV_FRAME_IMAGE_IDX,
+                    vk::ImageLayout::GENERAL,
+                );
+                // Drawn after the copy so shaders don't see it in the previous frame
+                if let Some(gui_frame) = gui_frame {
+                    let res = self.gui.paint(
+                        &mut frame,
+                        &mut self.texture_arena,
+                        &mut self.transfer,
+                        self.swapchain.get_current_image_view(),
+                        self.swapchain.extent(),
+                        gui_frame,
+                    );
+                    if let Err(err) = res {
+                        log::error!("{err:#}");
+                    }
+                }
 
                 match self.swapchain.submit_image(&self.queue, frame) {
                     Ok(_) => {}
@@ -480,6 +1318,9 @@ impl ApplicationHandler<UserEvent> for AppInit {
                     if let Err(err) = res {
                         log::error!("{err}");
                         self.video_recording = false;
+                    } else if !self.pause {
+                        let frame = Duration::from_secs_f64(1. / RECORD_FRAME_RATE as f64);
+                        self.seek(self.backup_time + frame);
                     }
                 }
 
@@ -491,10 +1332,24 @@ impl ApplicationHandler<UserEvent> for AppInit {
 
     fn user_event(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop, event: UserEvent) {
         match event {
+            UserEvent::Texture { path } => {
+                if let Err(err) = self.reload_texture(path) {
+                    log::error!("{err:#}");
+                }
+            }
+            UserEvent::TextureDecoded { path, texture } => {
+                if let Err(err) = self.land_decoded_texture(path, texture) {
+                    log::error!("{err:#}");
+                }
+            }
             UserEvent::Glsl { path } => {
                 match self.reload_shaders(path) {
-                    Err(err) => eprintln!("{err}"),
+                    Err(err) => {
+                        eprintln!("{err}");
+                        self.shader_error = Some(err.to_string());
+                    }
                     Ok(()) => {
+                        self.shader_error = None;
                         const ESC: &str = "\x1B[";
                         const RESET: &str = "\x1B[0m";
                         eprint!("\r{}42m{}K{}\r", ESC, ESC, RESET);
@@ -510,6 +1365,15 @@ impl ApplicationHandler<UserEvent> for AppInit {
         }
     }
 
+    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        // One recompilation for every name that came in since, outside of a frame
+        if std::mem::take(&mut self.params_added) {
+            if let Err(err) = self.reload_all_shaders() {
+                log::error!("{err:#}");
+            }
+        }
+    }
+
     fn exiting(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
         self.recorder.close_thread();
         if let Some(handle) = self.recorder.thread_handle.take() {
@@ -524,6 +1388,139 @@ impl ApplicationHandler<UserEvent> for AppInit {
     }
 }
 
+/// Uploads a texture from the project folder, new files get a slot and a named constant.
+fn load_user_texture(
+    texture_arena: &mut TextureArena,
+    shader_compiler: &mut ShaderCompiler,
+    user_textures: &mut AHashMap<PathBuf, u32>,
+    transfer: &mut TransferQueue,
+    path: &Path,
+    texture: &TextureData,
+) -> Result<u32> {
+    if let Some(&idx) = user_textures.get(path) {
+        texture_arena.replace_texture(transfer, idx, texture)?;
+        return Ok(idx);
+    }
+
+    let name = texture_constant_name(path);
+    if let Some(other) = user_textures
+        .keys()
+        .find(|other| texture_constant_name(other) == name)
+    {
+        bail!(
+            "{} and {} would both be named {name}",
+            path.display(),
+            other.display()
+        );
+    }
+    let idx = texture_arena.push_texture(transfer, texture)?;
+    shader_compiler.define(&name, &idx.to_string());
+    user_textures.insert(path.to_path_buf(), idx);
+    log::info!("Loaded {} as {name}", path.display());
+
+    Ok(idx)
+}
+
+/// Reserves a texture slot for a video, frames get uploaded while rendering.
+fn load_video(
+    texture_arena: &mut TextureArena,
+    shader_compiler: &mut ShaderCompiler,
+    user_textures: &mut AHashMap<PathBuf, u32>,
+    transfer: &mut TransferQueue,
+    path: &Path,
+) -> Result<VideoTexture> {
+    let info = VideoInfo::probe(path)?;
+    let mut video = VideoTexture::new(path, info, 0);
+    let texture = video.texture_data(&vec![0; info.frame_size()]);
+    video.slot = load_user_texture(
+        texture_arena,
+        shader_compiler,
+        user_textures,
+        transfer,
+        path,
+        &texture,
+    )?;
+    Ok(video)
+}
+
+/// Index of a parameter, new ones get a named constant.
+fn register_param(
+    params: &mut Params,
+    shader_compiler: &mut ShaderCompiler,
+    name: &str,
+    value: f32,
+) -> Result<usize> {
+    let (idx, is_new) = params.register(name, value)?;
+    if is_new {
+        shader_compiler.define(&param_constant_name(name), &idx.to_string());
+    }
+    Ok(idx)
+}
+
+/// Declares the parameters of a shader file, the first declaration defines their constant.
+fn declare_params(
+    params: &mut Params,
+    shader_compiler: &mut ShaderCompiler,
+    path: &Path,
+) -> Result<()> {
+    for desc in declared_params(path)? {
+        let (idx, is_new) = params.declare(&desc)?;
+        if is_new {
+            shader_compiler.define(&param_constant_name(&desc.name), &idx.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Length of the sound shader output, long enough for the whole recording.
+fn sound_duration(record_time: Option<Duration>) -> f32 {
+    record_time.map_or(SOUND_DURATION, |time| {
+        time.as_secs_f32().max(SOUND_DURATION)
+    })
+}
+
+/// Reserves a texture slot for the spectrum and waveform of an audio file.
+fn load_audio(
+    texture_arena: &mut TextureArena,
+    shader_compiler: &mut ShaderCompiler,
+    user_textures: &mut AHashMap<PathBuf, u32>,
+    transfer: &mut TransferQueue,
+    path: &Path,
+) -> Result<AudioTrack> {
+    let source = FileSource::new(path)?;
+    let mut track = AudioTrack::new(Box::new(source), 0);
+    track.slot = load_user_texture(
+        texture_arena,
+        shader_compiler,
+        user_textures,
+        transfer,
+        path,
+        &AudioTrack::empty_texture(),
+    )?;
+    Ok(track)
+}
+
+/// Records the upload of a texture that changes every frame into an existing slot.
+/// Streamed textures are small and needed by the same frame, so they stay on the
+/// graphics queue instead of waiting for the transfer queue.
+fn upload_frame_texture(
+    device: &Device,
+    texture_arena: &TextureArena,
+    frame: &mut FrameGuard,
+    slot: u32,
+    texture: &TextureData,
+) -> Result<()> {
+    let mut staging = device.create_host_buffer(
+        texture.data.len() as _,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        gpu_alloc::UsageFlags::UPLOAD,
+    )?;
+    staging.copy_from_slice(&texture.data);
+    texture_arena.record_upload(frame.command_buffer(), slot, staging.buffer, texture);
+    frame.defer_drop(staging);
+    Ok(())
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let event_loop = winit::event_loop::EventLoop::with_user_event().build()?;
@@ -531,14 +1528,29 @@ fn main() -> Result<()> {
     let Args {
         record_time,
         inner_size,
+        msaa_samples,
+        audio_input,
+        midi,
+        osc_port,
+        osc_feedback,
     } = parse_args()?;
 
     let shader_dir = PathBuf::new().join(SHADER_FOLDER);
     if !shader_dir.is_dir() {
         default_shaders::create_default_shaders(&shader_dir)?;
     }
+    create_folder(TEXTURE_FOLDER)?;
 
-    let mut app = App::new(event_loop.create_proxy(), record_time, inner_size);
+    let mut app = App::new(
+        event_loop.create_proxy(),
+        record_time,
+        inner_size,
+        msaa_samples,
+        audio_input,
+        midi,
+        osc_port,
+        osc_feedback,
+    );
     event_loop.run_app(&mut app)?;
     Ok(())
 }
@@ -547,6 +1559,11 @@ struct App {
     proxy: EventLoopProxy<UserEvent>,
     record_time: Option<Duration>,
     initial_window_size: Option<(u32, u32)>,
+    msaa_samples: Option<u32>,
+    audio_input: Option<Option<String>>,
+    midi: Option<Option<String>>,
+    osc_port: Option<u16>,
+    osc_feedback: Option<SocketAddr>,
     inner: AppEnum,
 }
 
@@ -555,11 +1572,21 @@ impl App {
         proxy: EventLoopProxy<UserEvent>,
         record_time: Option<Duration>,
         inner_size: Option<(u32, u32)>,
+        msaa_samples: Option<u32>,
+        audio_input: Option<Option<String>>,
+        midi: Option<Option<String>>,
+        osc_port: Option<u16>,
+        osc_feedback: Option<SocketAddr>,
     ) -> Self {
         Self {
             proxy,
             record_time,
             initial_window_size: inner_size,
+            msaa_samples,
+            audio_input,
+            midi,
+            osc_port,
+            osc_feedback,
             inner: AppEnum::Uninitialized,
         }
     }
@@ -587,6 +1614,11 @@ impl ApplicationHandler<UserEvent> for App {
                     self.proxy.clone(),
                     window_attributes,
                     self.record_time,
+                    self.msaa_samples,
+                    self.audio_input.take(),
+                    self.midi.take(),
+                    self.osc_port,
+                    self.osc_feedback,
                 )
                 .expect("Failed to create application");
 
@@ -0,0 +1,575 @@
+use std::{fmt, path::Path, time::Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use crossbeam_channel::{Receiver, Sender};
+use midir::{MidiInput, MidiInputConnection};
+
+use crate::{params::is_param_name, ParamDesc};
+
+/// Name of the virtual port and of the client other MIDI software sees.
+const CLIENT_NAME: &str = "pilka";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiControl {
+    /// Control change, knobs and faders.
+    Cc(u8),
+    /// Note velocity, 0 once released.
+    Note(u8),
+}
+
+impl fmt::Display for MidiControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiControl::Cc(number) => write!(f, "cc {number}"),
+            MidiControl::Note(number) => write!(f, "note {number}"),
+        }
+    }
+}
+
+/// Control or note message with its value mapped onto [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiMessage {
+    /// 1 to 16, as printed on controllers.
+    pub channel: u8,
+    pub control: MidiControl,
+    pub value: f32,
+}
+
+impl MidiMessage {
+    /// Reads a channel voice message, everything but notes and control changes is ignored.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let &[status, number, value] = bytes else {
+            return None;
+        };
+        let channel = (status & 0x0f) + 1;
+        let value = value.min(127) as f32 / 127.;
+        let (control, value) = match status & 0xf0 {
+            0x80 => (MidiControl::Note(number), 0.),
+            0x90 => (MidiControl::Note(number), value),
+            0xb0 => (MidiControl::Cc(number), value),
+            _ => return None,
+        };
+        Some(Self {
+            channel,
+            control,
+            value,
+        })
+    }
+}
+
+/// Parameter driven by a control, one line of the bindings file, e.g.
+/// `warp cc 74 channel=1 range=0..4 smoothing=0.1`.
+/// Without a channel the binding listens to all of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiBinding {
+    pub name: String,
+    pub control: MidiControl,
+    pub channel: Option<u8>,
+    /// Values the control's 0 and 1 map to, reversed ranges invert it.
+    pub range: (f32, f32),
+    /// Seconds the value takes to cover most of the way to a new position.
+    pub smoothing: f32,
+}
+
+impl MidiBinding {
+    pub fn new(name: &str, control: MidiControl, channel: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            control,
+            channel: Some(channel),
+            range: (0., 1.),
+            smoothing: 0.,
+        }
+    }
+
+    pub fn matches(&self, message: &MidiMessage) -> bool {
+        self.control == message.control
+            && self
+                .channel
+                .is_none_or(|channel| channel == message.channel)
+    }
+
+    pub fn map(&self, value: f32) -> f32 {
+        let (min, max) = self.range;
+        min + (max - min) * value
+    }
+}
+
+impl std::str::FromStr for MidiBinding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<_> = s.split_whitespace().collect();
+        let [name, kind, number, options @ ..] = &words[..] else {
+            bail!("Expected `<name> cc|note <number> [channel=1] [range=0..1] [smoothing=0]`");
+        };
+        if !is_param_name(name) {
+            bail!("`{name}` is not a valid parameter name");
+        }
+        let number: u8 = number
+            .parse()
+            .ok()
+            .filter(|&n| n < 128)
+            .with_context(|| format!("Invalid {kind} number `{number}`"))?;
+        let control = match *kind {
+            "cc" => MidiControl::Cc(number),
+            "note" => MidiControl::Note(number),
+            _ => bail!("Unknown control `{kind}`, expected cc or note"),
+        };
+
+        let mut binding = Self {
+            name: name.to_string(),
+            control,
+            channel: None,
+            range: (0., 1.),
+            smoothing: 0.,
+        };
+        for option in options {
+            let (key, value) = option
+                .split_once('=')
+                .with_context(|| format!("Expected `key=value`, got `{option}`"))?;
+            let invalid = || format!("Invalid {key} `{value}`");
+            match key {
+                "channel" => {
+                    let channel = value.parse().ok().filter(|c| (1..=16).contains(c));
+                    binding.channel = Some(channel.with_context(invalid)?);
+                }
+                "range" => {
+                    let (min, max) = value.split_once("..").with_context(invalid)?;
+                    binding.range = (
+                        min.parse().with_context(invalid)?,
+                        max.parse().with_context(invalid)?,
+                    );
+                }
+                "smoothing" => {
+                    let smoothing = value.parse().ok().filter(|&s: &f32| s >= 0.);
+                    binding.smoothing = smoothing.with_context(invalid)?;
+                }
+                _ => bail!("Unknown option `{key}`"),
+            }
+        }
+        Ok(binding)
+    }
+}
+
+impl fmt::Display for MidiBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.control)?;
+        if let Some(channel) = self.channel {
+            write!(f, " channel={channel}")?;
+        }
+        write!(f, " range={}..{}", self.range.0, self.range.1)?;
+        if self.smoothing > 0. {
+            write!(f, " smoothing={}", self.smoothing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bindings of a project with the smoothed value of each one.
+#[derive(Debug, Default)]
+pub struct MidiBindings {
+    bindings: Vec<MidiBinding>,
+    targets: Vec<f32>,
+    values: Vec<f32>,
+}
+
+impl MidiBindings {
+    /// Reads a bindings file, `#` starts a comment. A missing file has no bindings.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut bindings = Self::default();
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(bindings),
+            Err(err) => return Err(err).context(format!("Failed to read {}", path.display())),
+        };
+        for (line_idx, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let binding = line
+                .parse()
+                .with_context(|| format!("{}:{}", path.display(), line_idx + 1))?;
+            bindings.push(binding);
+        }
+        Ok(bindings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut source =
+            String::from("# <name> cc|note <number> [channel=1] [range=0..1] [smoothing=0]\n");
+        for binding in &self.bindings {
+            source.push_str(&format!("{binding}\n"));
+        }
+        std::fs::write(path, source).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn push(&mut self, binding: MidiBinding) {
+        let value = binding.map(0.);
+        self.bindings.push(binding);
+        self.targets.push(value);
+        self.values.push(value);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MidiBinding> {
+        self.bindings.iter()
+    }
+
+    pub fn find(&self, message: &MidiMessage) -> Option<&MidiBinding> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.matches(message))
+    }
+
+    /// Binds the control of the message to `target` over its range, or to a new parameter
+    /// named after the control. Controls already bound are left as they are.
+    pub fn learn(
+        &mut self,
+        message: &MidiMessage,
+        target: Option<&ParamDesc>,
+    ) -> Option<MidiBinding> {
+        if let Some(binding) = self.find(message) {
+            log::info!("{} already drives {}", message.control, binding.name);
+            return None;
+        }
+        let name = match (target, message.control) {
+            (Some(target), _) => target.name.clone(),
+            (None, MidiControl::Cc(number)) => format!("cc{number}"),
+            (None, MidiControl::Note(number)) => format!("note{number}"),
+        };
+        let mut binding = MidiBinding::new(&name, message.control, message.channel);
+        if let Some(target) = target {
+            binding.range = target.range;
+        }
+        self.push(binding.clone());
+        Some(binding)
+    }
+
+    /// Moves the targets of every binding listening to the message.
+    pub fn handle(&mut self, message: &MidiMessage) {
+        for (binding, target) in self.bindings.iter().zip(&mut self.targets) {
+            if binding.matches(message) {
+                *target = binding.map(message.value);
+            }
+        }
+    }
+
+    /// Advances the smoothing by `dt` seconds, returns the name and value of the bindings
+    /// that moved. Parameters keep values set elsewhere until their control moves again.
+    pub fn update(&mut self, dt: f32) -> impl Iterator<Item = (&str, f32)> {
+        self.bindings
+            .iter()
+            .zip(&self.targets)
+            .zip(&mut self.values)
+            .filter_map(move |((binding, target), value)| {
+                let next = if binding.smoothing > 0. {
+                    *value + (target - *value) * (1. - (-dt.max(0.) / binding.smoothing).exp())
+                } else {
+                    *target
+                };
+                (next != *value).then(|| {
+                    *value = next;
+                    (binding.name.as_str(), next)
+                })
+            })
+    }
+}
+
+/// Open MIDI input port, messages get queued until the next frame.
+pub struct MidiPort {
+    pub name: String,
+    messages: Receiver<MidiMessage>,
+    _connection: MidiInputConnection<Sender<MidiMessage>>,
+}
+
+impl MidiPort {
+    /// Connects to the first input port whose name contains `port`, or the first port at all.
+    /// `virtual` creates a port named `pilka` that other software can connect to instead.
+    pub fn new(port: Option<&str>) -> Result<Self> {
+        let input = MidiInput::new(CLIENT_NAME)?;
+        let (tx, messages) = crossbeam_channel::unbounded();
+        let callback = |_stamp: u64, bytes: &[u8], tx: &mut Sender<MidiMessage>| {
+            if let Some(message) = MidiMessage::parse(bytes) {
+                let _ = tx.send(message);
+            }
+        };
+
+        if port == Some("virtual") {
+            return Self::new_virtual(input, callback, tx, messages);
+        }
+        let ports = input.ports();
+        let (port, name) = ports
+            .iter()
+            .filter_map(|p| Some((p, input.port_name(p).ok()?)))
+            .find(|(_, name)| port.is_none_or(|pattern| name.contains(pattern)))
+            .with_context(|| match port {
+                Some(pattern) => format!("No MIDI input port matching `{pattern}`"),
+                None => "No MIDI input port".into(),
+            })?;
+        let connection = input
+            .connect(port, CLIENT_NAME, callback, tx)
+            .map_err(|err| anyhow!("Failed to connect to {name}: {err}"))?;
+        log::info!("Listening to MIDI from {name}");
+
+        Ok(Self {
+            name,
+            messages,
+            _connection: connection,
+        })
+    }
+
+    #[cfg(unix)]
+    fn new_virtual(
+        input: MidiInput,
+        callback: impl FnMut(u64, &[u8], &mut Sender<MidiMessage>) + Send + 'static,
+        tx: Sender<MidiMessage>,
+        messages: Receiver<MidiMessage>,
+    ) -> Result<Self> {
+        use midir::os::unix::VirtualInput;
+
+        let connection = input
+            .create_virtual(CLIENT_NAME, callback, tx)
+            .map_err(|err| anyhow!("Failed to create a virtual MIDI port: {err}"))?;
+        log::info!("Listening to MIDI on the virtual port {CLIENT_NAME}");
+        Ok(Self {
+            name: CLIENT_NAME.into(),
+            messages,
+            _connection: connection,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new_virtual(
+        _input: MidiInput,
+        _callback: impl FnMut(u64, &[u8], &mut Sender<MidiMessage>) + Send + 'static,
+        _tx: Sender<MidiMessage>,
+        _messages: Receiver<MidiMessage>,
+    ) -> Result<Self> {
+        bail!("Virtual MIDI ports are only available on Linux and macOS")
+    }
+
+    /// Messages received since the last call.
+    pub fn poll(&self) -> impl Iterator<Item = MidiMessage> + '_ {
+        self.messages.try_iter()
+    }
+}
+
+/// MIDI input of a project driving the parameters of its bindings.
+pub struct MidiController {
+    port: MidiPort,
+    pub bindings: MidiBindings,
+    /// Learn mode, the next moved control without a binding gets one.
+    pub learning: bool,
+    last_update: Instant,
+}
+
+impl MidiController {
+    pub fn new(port: Option<&str>, bindings_path: &Path) -> Result<Self> {
+        Ok(Self {
+            port: MidiPort::new(port)?,
+            bindings: MidiBindings::load(bindings_path)?,
+            learning: false,
+            last_update: Instant::now(),
+        })
+    }
+
+    pub fn port_name(&self) -> &str {
+        &self.port.name
+    }
+
+    /// Applies the messages received since the last call, returns the binding learned from them.
+    /// A learned control drives `target` over its range, or a new parameter named after it.
+    pub fn poll(&mut self, target: Option<&ParamDesc>) -> Option<MidiBinding> {
+        let mut learned = None;
+        for message in self.port.poll() {
+            // Releases would bind notes as soon as they are let go
+            if self.learning && message.value > 0. {
+                self.learning = false;
+                learned = self.bindings.learn(&message, target);
+            }
+            self.bindings.handle(&message);
+        }
+        learned
+    }
+
+    /// Smoothed values of the bindings that moved since the previous call.
+    pub fn values(&mut self) -> impl Iterator<Item = (&str, f32)> {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+        self.bindings.update(dt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(channel: u8, number: u8, value: f32) -> MidiMessage {
+        MidiMessage {
+            channel,
+            control: MidiControl::Cc(number),
+            value,
+        }
+    }
+
+    #[test]
+    fn parse_messages() {
+        assert_eq!(MidiMessage::parse(&[0xb0, 74, 127]), Some(cc(1, 74, 1.)));
+        assert_eq!(
+            MidiMessage::parse(&[0x99, 36, 0]),
+            Some(MidiMessage {
+                channel: 10,
+                control: MidiControl::Note(36),
+                value: 0.,
+            })
+        );
+        // Note off keeps no velocity
+        let off = MidiMessage::parse(&[0x8f, 36, 64]).unwrap();
+        assert_eq!((off.channel, off.value), (16, 0.));
+        // Pitch bend, clock and truncated messages
+        assert_eq!(MidiMessage::parse(&[0xe0, 0, 64]), None);
+        assert_eq!(MidiMessage::parse(&[0xf8]), None);
+        assert_eq!(MidiMessage::parse(&[0xb0, 74]), None);
+    }
+
+    #[test]
+    fn binding_round_trip() {
+        for line in [
+            "warp cc 74 range=0..4 smoothing=0.1",
+            "kick note 36 channel=10 range=0..1",
+            "fade cc 1 channel=2 range=1..-1",
+        ] {
+            let binding: MidiBinding = line.parse().unwrap();
+            assert_eq!(binding.to_string(), line);
+            assert_eq!(binding.to_string().parse::<MidiBinding>().unwrap(), binding);
+        }
+        // Omitted options get their defaults
+        let binding: MidiBinding = "warp cc 74".parse().unwrap();
+        assert_eq!(binding.channel, None);
+        assert_eq!(binding.range, (0., 1.));
+        assert_eq!(binding.smoothing, 0.);
+    }
+
+    #[test]
+    fn binding_errors() {
+        for line in [
+            "warp cc",
+            "warp pb 74",
+            "warp cc 128",
+            "1warp cc 74",
+            "warp cc 74 channel=17",
+            "warp cc 74 range=0",
+            "warp cc 74 smoothing=-1",
+            "warp cc 74 speed=2",
+        ] {
+            assert!(line.parse::<MidiBinding>().is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn map_and_match() {
+        let mut binding: MidiBinding = "fade cc 1 channel=2 range=4..-4".parse().unwrap();
+        assert_eq!(binding.map(0.), 4.);
+        assert_eq!(binding.map(0.5), 0.);
+        assert_eq!(binding.map(1.), -4.);
+        assert!(binding.matches(&cc(2, 1, 0.)));
+        assert!(!binding.matches(&cc(3, 1, 0.)));
+        assert!(!binding.matches(&cc(2, 2, 0.)));
+        binding.channel = None;
+        assert!(binding.matches(&cc(3, 1, 0.)));
+    }
+
+    #[test]
+    fn update_without_smoothing() {
+        let mut bindings = MidiBindings::default();
+        bindings.push("warp cc 74 range=0..4".parse().unwrap());
+        // Nothing moved yet
+        assert_eq!(bindings.update(0.1).count(), 0);
+        bindings.handle(&cc(1, 74, 0.5));
+        assert_eq!(bindings.update(0.1).collect::<Vec<_>>(), [("warp", 2.)]);
+        assert_eq!(bindings.update(0.1).count(), 0);
+    }
+
+    #[test]
+    fn update_smoothing() {
+        let mut bindings = MidiBindings::default();
+        bindings.push("warp cc 74 smoothing=0.1".parse().unwrap());
+        bindings.handle(&cc(1, 74, 1.));
+        let mut last = 0.;
+        for _ in 0..10 {
+            let (_, value) = bindings.update(0.05).next().unwrap();
+            assert!(value > last && value < 1., "{value} after {last}");
+            last = value;
+        }
+        // A time constant gets most of the way there
+        let mut bindings = MidiBindings::default();
+        bindings.push("warp cc 74 smoothing=0.1".parse().unwrap());
+        bindings.handle(&cc(1, 74, 1.));
+        let (_, value) = bindings.update(0.1).next().unwrap();
+        assert!((value - (1. - (-1f32).exp())).abs() < 1e-6, "{value}");
+    }
+
+    #[test]
+    fn learn_binds_once() {
+        let mut bindings = MidiBindings::default();
+        let learned = bindings.learn(&cc(3, 21, 1.), None).unwrap();
+        assert_eq!(learned.to_string(), "cc21 cc 21 channel=3 range=0..1");
+        assert!(bindings.learn(&cc(3, 21, 1.), None).is_none());
+
+        let mut target = ParamDesc::new("warp", 1.);
+        target.range = (-2., 2.);
+        let note = MidiMessage {
+            channel: 10,
+            control: MidiControl::Note(36),
+            value: 1.,
+        };
+        let learned = bindings.learn(&note, Some(&target)).unwrap();
+        assert_eq!(learned.to_string(), "warp note 36 channel=10 range=-2..2");
+        assert_eq!(bindings.iter().count(), 2);
+    }
+
+    #[test]
+    fn bindings_file() {
+        let path = std::env::temp_dir().join(format!("pilka-midi-{}.txt", std::process::id()));
+        std::fs::write(&path, "# comment\n\nwarp cc 74 range=0..4 # trailing\n").unwrap();
+        let bindings = MidiBindings::load(&path).unwrap();
+        assert_eq!(bindings.iter().count(), 1);
+        bindings.save(&path).unwrap();
+        let reloaded = MidiBindings::load(&path).unwrap();
+        assert!(bindings.iter().eq(reloaded.iter()));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(MidiBindings::load(&path).unwrap().iter().count(), 0);
+    }
+
+    /// Sends through a loopback into `--midi virtual`, e.g. `cargo test -- --ignored virtual_port`.
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "needs a running ALSA sequencer or CoreMIDI"]
+    fn virtual_port() {
+        let port = MidiPort::new(Some("virtual")).unwrap();
+        let output = midir::MidiOutput::new("pilka-test").unwrap();
+        let target = output
+            .ports()
+            .into_iter()
+            .find(|p| {
+                output
+                    .port_name(p)
+                    .is_ok_and(|name| name.contains(CLIENT_NAME))
+            })
+            .expect("virtual port not listed");
+        let mut connection = output.connect(&target, "pilka-test").unwrap();
+        connection.send(&[0xb1, 74, 127]).unwrap();
+        connection.send(&[0xf8]).unwrap();
+
+        let deadline = Instant::now() + std::time::Duration::from_secs(2);
+        let mut received = vec![];
+        while received.is_empty() && Instant::now() < deadline {
+            received.extend(port.poll());
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(received, [cc(2, 74, 1.)]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use winit::event::{MouseButton, MouseScrollDelta};
+
+use crate::{Device, DeviceBuffer, FrameGuard};
+
+pub const MOUSE_LEFT: u32 = 1;
+pub const MOUSE_RIGHT: u32 = 2;
+pub const MOUSE_MIDDLE: u32 = 4;
+/// Touchpads scroll in pixels, the wheel accumulates lines.
+const PIXELS_PER_LINE: f32 = 20.;
+
+/// Mouse state shaders read through `pc.mouse_state`. Positions are in pixels with
+/// the origin in the bottom-left corner, like `in_uv * pc.resolution`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MouseUniform {
+    pub position: [f32; 2],
+    /// `position` over the resolution, 0..1.
+    pub normalized: [f32; 2],
+    /// Where the last button got pressed.
+    pub click: [f32; 2],
+    pub click_normalized: [f32; 2],
+    /// `position - click` while a button is held, zero otherwise.
+    pub drag: [f32; 2],
+    /// Scrolled lines since the start, y goes up when scrolling away from the user.
+    pub wheel: [f32; 2],
+    /// Shadertoy's `iMouse`: the last left-dragged position, then the left click with z
+    /// negative once the button is up and w positive in the frame of the click only.
+    pub shadertoy: [f32; 4],
+    /// Held buttons, `MOUSE_LEFT | MOUSE_RIGHT | MOUSE_MIDDLE`.
+    pub buttons: u32,
+    /// Buttons pressed since the previous frame.
+    pub pressed: u32,
+    /// Buttons released since the previous frame.
+    pub released: u32,
+    /// A button is held and the cursor left the click position.
+    pub dragging: u32,
+}
+
+unsafe impl bytemuck::Zeroable for MouseUniform {}
+unsafe impl bytemuck::Pod for MouseUniform {}
+
+/// Mouse state tracked from window events, it keeps going while the timeline is paused.
+pub struct Mouse {
+    position: [f32; 2],
+    click: [f32; 2],
+    /// Shadertoy only follows the left button, right drags look around with the camera.
+    left_click: [f32; 2],
+    /// Shadertoy keeps the position of the last left drag once the button is up.
+    drag_position: [f32; 2],
+    wheel: [f32; 2],
+    buttons: u32,
+    pressed: u32,
+    released: u32,
+    pub buffer: DeviceBuffer,
+}
+
+impl Mouse {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let buffer = device.create_device_buffer(
+            size_of::<MouseUniform>() as _,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+        device.name_object(buffer.buffer, "Mouse Buffer");
+        Ok(Self {
+            position: [0.; 2],
+            click: [0.; 2],
+            left_click: [0.; 2],
+            drag_position: [0.; 2],
+            wheel: [0.; 2],
+            buttons: 0,
+            pressed: 0,
+            released: 0,
+            buffer,
+        })
+    }
+
+    /// `x` and `y` are in pixels from the bottom-left corner.
+    pub fn cursor_moved(&mut self, x: f32, y: f32) {
+        self.position = [x, y];
+        if self.buttons & MOUSE_LEFT != 0 {
+            self.drag_position = self.position;
+        }
+    }
+
+    pub fn button(&mut self, button: MouseButton, pressed: bool) {
+        let bit = match button {
+            MouseButton::Left => MOUSE_LEFT,
+            MouseButton::Right => MOUSE_RIGHT,
+            MouseButton::Middle => MOUSE_MIDDLE,
+            _ => return,
+        };
+        if pressed {
+            self.click = self.position;
+            if bit == MOUSE_LEFT {
+                self.left_click = self.position;
+                self.drag_position = self.position;
+            }
+            self.buttons |= bit;
+            self.pressed |= bit;
+        } else {
+            self.buttons &= !bit;
+            self.released |= bit;
+        }
+    }
+
+    pub fn scroll(&mut self, delta: MouseScrollDelta) {
+        let [x, y] = match delta {
+            MouseScrollDelta::LineDelta(x, y) => [x, y],
+            MouseScrollDelta::PixelDelta(delta) => [
+                delta.x as f32 / PIXELS_PER_LINE,
+                delta.y as f32 / PIXELS_PER_LINE,
+            ],
+        };
+        self.wheel[0] += x;
+        self.wheel[1] += y;
+    }
+
+    pub fn uniform(&self, resolution: [f32; 2]) -> MouseUniform {
+        let [width, height] = resolution.map(|v| v.max(1.));
+        let normalize = |[x, y]: [f32; 2]| [x / width, y / height];
+        let held = self.buttons != 0;
+        let drag = if held {
+            [
+                self.position[0] - self.click[0],
+                self.position[1] - self.click[1],
+            ]
+        } else {
+            [0.; 2]
+        };
+        let left_held = self.buttons & MOUSE_LEFT != 0;
+        let left_pressed = self.pressed & MOUSE_LEFT != 0;
+        MouseUniform {
+            position: self.position,
+            normalized: normalize(self.position),
+            click: self.click,
+            click_normalized: normalize(self.click),
+            drag,
+            wheel: self.wheel,
+            shadertoy: [
+                self.drag_position[0],
+                self.drag_position[1],
+                if left_held {
+                    self.left_click[0]
+                } else {
+                    -self.left_click[0]
+                },
+                if left_pressed {
+                    self.left_click[1]
+                } else {
+                    -self.left_click[1]
+                },
+            ],
+            buttons: self.buttons,
+            pressed: self.pressed,
+            released: self.released,
+            dragging: (held && self.position != self.click) as u32,
+        }
+    }
+
+    /// Copies the state into its buffer ahead of the frame's passes, presses and releases
+    /// are seen by one frame.
+    pub fn record_upload(&mut self, frame: &FrameGuard, resolution: [f32; 2]) {
+        let uniform = self.uniform(resolution);
+        frame.update_buffer(self.buffer.buffer, bytemuck::bytes_of(&uniform));
+        (self.pressed, self.released) = (0, 0);
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::{Device, Image};
+
+pub struct MsaaTarget {
+    pub samples: vk::SampleCountFlags,
+    color: Option<(Image, vk::ImageView)>,
+    depth: Option<(Image, vk::ImageView)>,
+    color_format: vk::Format,
+    depth_format: vk::Format,
+    extent: vk::Extent2D,
+    device: Arc<Device>,
+}
+
+impl MsaaTarget {
+    pub fn new(
+        device: &Arc<Device>,
+        extent: vk::Extent2D,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self> {
+        let mut target = Self {
+            samples,
+            color: None,
+            depth: None,
+            color_format,
+            depth_format,
+            extent,
+            device: device.clone(),
+        };
+        target.recreate()?;
+        Ok(target)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+
+    pub fn color(&self) -> Option<(&vk::Image, &vk::ImageView)> {
+        self.color
+            .as_ref()
+            .map(|(image, view)| (&image.image, view))
+    }
+
+    pub fn depth(&self) -> Option<(&vk::Image, &vk::ImageView)> {
+        self.depth
+            .as_ref()
+            .map(|(image, view)| (&image.image, view))
+    }
+
+    pub fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        self.extent = extent;
+        self.recreate()
+    }
+
+    pub fn set_samples(&mut self, samples: vk::SampleCountFlags) -> Result<()> {
+        self.samples = samples;
+        self.recreate()
+    }
+
+    fn recreate(&mut self) -> Result<()> {
+        self.destroy();
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let usage = vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+        self.color = Some(self.create_image(
+            self.color_format,
+            usage | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            "MSAA Color Image",
+        )?);
+        if self.depth_format != vk::Format::UNDEFINED {
+            self.depth = Some(self.create_image(
+                self.depth_format,
+                usage | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                "MSAA Depth Image",
+            )?);
+        }
+
+        Ok(())
+    }
+
+    fn create_image(
+        &self,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        name: &str,
+    ) -> Result<(Image, vk::ImageView)> {
+        let info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .usage(usage)
+            .samples(self.samples)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL);
+        let image = Image::new(
+            &self.device,
+            &info,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )?;
+        let view = self.device.create_2d_view(&image.image, format)?;
+        self.device.name_object(image.image, name);
+        self.device.name_object(view, &format!("{name} View"));
+        Ok((image, view))
+    }
+
+    fn destroy(&mut self) {
+        for (mut image, view) in self.color.take().into_iter().chain(self.depth.take()) {
+            unsafe { self.device.destroy_image_view(view, None) };
+            image.desctroy(&self.device);
+        }
+    }
+}
+
+impl Drop for MsaaTarget {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
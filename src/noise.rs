@@ -0,0 +1,317 @@
+use anyhow::{bail, Context, Result};
+use ash::vk;
+
+use crate::TextureData;
+
+const MAX_SIZE: u32 = 8192;
+/// Blue noise costs a full scan of the texture per texel, larger sizes take too long.
+const MAX_BLUE_NOISE_TEXELS: u32 = 128 * 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    White,
+    Value,
+    Perlin,
+    Blue,
+}
+
+/// Tileable noise texture described by a `.noise` file, e.g.
+/// `perlin size=256 period=8 octaves=4 seed=1 channels=4`.
+/// Every channel is an independent noise with its own seed.
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseDesc {
+    pub kind: NoiseKind,
+    pub width: u32,
+    pub height: u32,
+    pub seed: u32,
+    pub channels: u32,
+    /// Lattice cells across the tile for the first octave of value and Perlin noise.
+    pub period: u32,
+    pub octaves: u32,
+}
+
+impl Default for NoiseDesc {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::White,
+            width: 256,
+            height: 256,
+            seed: 0,
+            channels: 1,
+            period: 8,
+            octaves: 1,
+        }
+    }
+}
+
+impl std::str::FromStr for NoiseDesc {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(str::split_whitespace);
+        let kind = match words.next() {
+            Some("white") => NoiseKind::White,
+            Some("value") => NoiseKind::Value,
+            Some("perlin") => NoiseKind::Perlin,
+            Some("blue") => NoiseKind::Blue,
+            Some(kind) => bail!("Unknown noise `{kind}`, expected white, value, perlin or blue"),
+            None => bail!("Empty noise description"),
+        };
+
+        let mut desc = Self {
+            kind,
+            ..Default::default()
+        };
+        if kind == NoiseKind::Blue {
+            desc.width = 64;
+            desc.height = 64;
+        }
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                bail!("Expected `key=value`, got `{word}`");
+            };
+            let number = || -> Result<u32> {
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid {key} `{value}`"))
+            };
+            match key {
+                "size" => {
+                    let (width, height) = value.split_once('x').unwrap_or((value, value));
+                    desc.width = width.parse().context("Invalid width")?;
+                    desc.height = height.parse().context("Invalid height")?;
+                }
+                "seed" => desc.seed = number()?,
+                "channels" => desc.channels = number()?,
+                "period" => desc.period = number()?,
+                "octaves" => desc.octaves = number()?,
+                _ => bail!("Unknown noise parameter `{key}`"),
+            }
+        }
+
+        if !(1..=MAX_SIZE).contains(&desc.width) || !(1..=MAX_SIZE).contains(&desc.height) {
+            bail!("Noise size must be between 1 and {MAX_SIZE}");
+        }
+        if ![1, 2, 4].contains(&desc.channels) {
+            bail!("Noise can have 1, 2 or 4 channels");
+        }
+        if desc.period == 0 {
+            bail!("Noise period must be greater than zero");
+        }
+        if !(1..=16).contains(&desc.octaves) {
+            bail!("Noise can have 1 to 16 octaves");
+        }
+        // Every octave doubles the period, the last one still needs a texel per cell
+        let last_period = desc.period.checked_mul(1 << (desc.octaves - 1));
+        if matches!(kind, NoiseKind::Value | NoiseKind::Perlin)
+            && !last_period.is_some_and(|period| period <= desc.width.max(desc.height))
+        {
+            bail!(
+                "Noise period {} over {} octaves gets finer than the {}x{} texture",
+                desc.period,
+                desc.octaves,
+                desc.width,
+                desc.height
+            );
+        }
+        if kind == NoiseKind::Blue && desc.width * desc.height > MAX_BLUE_NOISE_TEXELS {
+            bail!("Blue noise is limited to {MAX_BLUE_NOISE_TEXELS} texels");
+        }
+        Ok(desc)
+    }
+}
+
+impl NoiseDesc {
+    pub fn generate(&self) -> TextureData {
+        let texels = (self.width * self.height) as usize;
+        let channels: Vec<Vec<u8>> = (0..self.channels)
+            .map(|channel| {
+                let seed = hash(self.seed ^ channel.wrapping_mul(0x9e37_79b9));
+                match self.kind {
+                    NoiseKind::White => (0..texels as u32).map(|i| hash(i ^ seed) as u8).collect(),
+                    NoiseKind::Value | NoiseKind::Perlin => self.fbm(seed),
+                    NoiseKind::Blue => void_and_cluster(self.width, self.height, seed),
+                }
+            })
+            .collect();
+
+        let data: Vec<u8> = (0..texels)
+            .flat_map(|i| channels.iter().map(move |channel| channel[i]))
+            .collect();
+        let format = match self.channels {
+            1 => vk::Format::R8_UNORM,
+            2 => vk::Format::R8G8_UNORM,
+            _ => vk::Format::R8G8B8A8_UNORM,
+        };
+        let extent = vk::Extent3D {
+            width: self.width,
+            height: self.height,
+            depth: 1,
+        };
+        TextureData::from_levels(format, extent, [&data[..]])
+    }
+
+    fn fbm(&self, seed: u32) -> Vec<u8> {
+        let mut values = vec![0f32; (self.width * self.height) as usize];
+        let mut amplitude = 1.;
+        let mut total_amplitude = 0.;
+        for octave in 0..self.octaves {
+            let period = self.period << octave;
+            let seed = hash(seed.wrapping_add(octave));
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let px = x as f32 / self.width as f32 * period as f32;
+                    let py = y as f32 / self.height as f32 * period as f32;
+                    let value = match self.kind {
+                        NoiseKind::Perlin => perlin(px, py, period, seed) * 0.5f32.sqrt() + 0.5,
+                        _ => value_noise(px, py, period, seed),
+                    };
+                    values[(y * self.width + x) as usize] += value * amplitude;
+                }
+            }
+            total_amplitude += amplitude;
+            amplitude *= 0.5;
+        }
+        values
+            .into_iter()
+            .map(|v| (v / total_amplitude * 255.).round().clamp(0., 255.) as u8)
+            .collect()
+    }
+}
+
+/// Integer hash by Chris Wellons, good enough to seed every texel independently.
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+fn lattice_hash(x: u32, y: u32, period: u32, seed: u32) -> u32 {
+    hash((x % period) ^ hash((y % period) ^ seed))
+}
+
+fn quintic(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Lattice values wrap every `period` cells so the tile repeats seamlessly.
+fn value_noise(x: f32, y: f32, period: u32, seed: u32) -> f32 {
+    let (ix, iy) = (x.floor() as u32, y.floor() as u32);
+    let (fx, fy) = (quintic(x.fract()), quintic(y.fract()));
+    let corner = |dx, dy| lattice_hash(ix + dx, iy + dy, period, seed) as f32 / u32::MAX as f32;
+    lerp(
+        lerp(corner(0, 0), corner(1, 0), fx),
+        lerp(corner(0, 1), corner(1, 1), fx),
+        fy,
+    )
+}
+
+/// Gradient noise in [-sqrt(0.5), sqrt(0.5)], gradients wrap every `period` cells.
+fn perlin(x: f32, y: f32, period: u32, seed: u32) -> f32 {
+    let (ix, iy) = (x.floor() as u32, y.floor() as u32);
+    let (fx, fy) = (x.fract(), y.fract());
+    let corner = |dx: u32, dy: u32| {
+        let angle = lattice_hash(ix + dx, iy + dy, period, seed) as f32 / u32::MAX as f32
+            * std::f32::consts::TAU;
+        let (sin, cos) = angle.sin_cos();
+        cos * (fx - dx as f32) + sin * (fy - dy as f32)
+    };
+    let (u, v) = (quintic(fx), quintic(fy));
+    lerp(
+        lerp(corner(0, 0), corner(1, 0), u),
+        lerp(corner(0, 1), corner(1, 1), u),
+        v,
+    )
+}
+
+/// Ranks texels with Ulichney's void-and-cluster method, the ranks spread evenly
+/// over the tile at every threshold.
+fn void_and_cluster(width: u32, height: u32, seed: u32) -> Vec<u8> {
+    const SIGMA: f32 = 1.5;
+    const RADIUS: i32 = 5;
+
+    let (w, h) = (width as i32, height as i32);
+    let count = (width * height) as usize;
+    let kernel: Vec<(i32, i32, f32)> = (-RADIUS..=RADIUS)
+        .flat_map(|dy| (-RADIUS..=RADIUS).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| {
+            let weight = (-((dx * dx + dy * dy) as f32) / (2. * SIGMA * SIGMA)).exp();
+            (dx, dy, weight)
+        })
+        .collect();
+
+    let mut pattern = vec![false; count];
+    let mut energy = vec![0f32; count];
+    let toggle = |pattern: &mut [bool], energy: &mut [f32], i: usize| {
+        pattern[i] = !pattern[i];
+        let sign = if pattern[i] { 1. } else { -1. };
+        let (x, y) = (i as i32 % w, i as i32 / w);
+        for &(dx, dy, weight) in &kernel {
+            let (kx, ky) = ((x + dx).rem_euclid(w), (y + dy).rem_euclid(h));
+            energy[(ky * w + kx) as usize] += sign * weight;
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // Initial pattern: a tenth of the texels at random positions
+    let initial = (count / 10).max(1);
+    let mut ones = 0;
+    let mut i = 0;
+    while ones < initial {
+        let texel = hash(seed ^ hash(i)) as usize % count;
+        if !pattern[texel] {
+            toggle(&mut pattern, &mut energy, texel);
+            ones += 1;
+        }
+        i += 1;
+    }
+    // Move points from clusters into voids until the pattern settles
+    for _ in 0..count {
+        let cluster = tightest_cluster(&pattern, &energy).unwrap();
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = largest_void(&pattern, &energy).unwrap();
+        if void == cluster {
+            toggle(&mut pattern, &mut energy, cluster);
+            break;
+        }
+        toggle(&mut pattern, &mut energy, void);
+    }
+
+    let mut ranks = vec![0; count];
+    let (prototype, prototype_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..ones).rev() {
+        let cluster = tightest_cluster(&pattern, &energy).unwrap();
+        toggle(&mut pattern, &mut energy, cluster);
+        ranks[cluster] = rank;
+    }
+    let (mut pattern, mut energy) = (prototype, prototype_energy);
+    for rank in ones..count {
+        let void = largest_void(&pattern, &energy).unwrap();
+        toggle(&mut pattern, &mut energy, void);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank * 256 / count) as u8)
+        .collect()
+}
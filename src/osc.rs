@@ -0,0 +1,189 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::Receiver;
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime, OscType};
+
+const PARAM_ADDRESS: &str = "/pilka/param/";
+const SEEK_ADDRESS: &str = "/pilka/time/seek";
+const PAUSE_ADDRESS: &str = "/pilka/pause";
+const TIME_ADDRESS: &str = "/pilka/time";
+/// Controllers don't need the state more often than this.
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Remote control received over OSC.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscCommand {
+    /// `/pilka/param/<name> <value>`
+    Param { name: String, value: f32 },
+    /// `/pilka/time/seek <seconds>`
+    Seek(f32),
+    /// `/pilka/pause [0|1]`, toggles without an argument.
+    Pause(Option<bool>),
+}
+
+impl OscCommand {
+    pub fn parse(message: &OscMessage) -> Result<Self> {
+        let address = message.addr.as_str();
+        let number = || match message.args.first() {
+            Some(arg) => arg_f32(arg)
+                .filter(|value| value.is_finite())
+                .with_context(|| format!("{address} expects a number")),
+            None => bail!("{address} expects an argument"),
+        };
+        let command = if let Some(name) = address.strip_prefix(PARAM_ADDRESS) {
+            OscCommand::Param {
+                name: name.to_string(),
+                value: number()?,
+            }
+        } else if address == SEEK_ADDRESS {
+            OscCommand::Seek(number()?.max(0.))
+        } else if address == PAUSE_ADDRESS {
+            let pause = match message.args.first() {
+                Some(_) => Some(number()? != 0.),
+                None => None,
+            };
+            OscCommand::Pause(pause)
+        } else {
+            bail!("Unknown OSC address {address}");
+        };
+        Ok(command)
+    }
+}
+
+/// Arguments as sent by common controllers, which use ints, floats or booleans.
+fn arg_f32(arg: &OscType) -> Option<f32> {
+    match *arg {
+        OscType::Float(value) => Some(value),
+        OscType::Double(value) => Some(value as f32),
+        OscType::Int(value) => Some(value as f32),
+        OscType::Long(value) => Some(value as f32),
+        OscType::Bool(value) => Some(value as u32 as f32),
+        _ => None,
+    }
+}
+
+fn collect_messages(packet: OscPacket, messages: &mut Vec<OscMessage>) {
+    match packet {
+        OscPacket::Message(message) => messages.push(message),
+        OscPacket::Bundle(bundle) => bundle
+            .content
+            .into_iter()
+            .for_each(|packet| collect_messages(packet, messages)),
+    }
+}
+
+/// UDP socket receiving commands, and sending the playback state and parameters back
+/// to `feedback` if there is one.
+pub struct OscServer {
+    socket: UdpSocket,
+    commands: Receiver<OscCommand>,
+    feedback: Option<SocketAddr>,
+    last_feedback: Instant,
+    /// Parameters as last sent, only changes go out.
+    sent: Vec<(String, f32)>,
+}
+
+impl OscServer {
+    pub fn new(port: u16, feedback: Option<SocketAddr>) -> Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .with_context(|| format!("Failed to listen for OSC on port {port}"))?;
+        let receiver = socket.try_clone()?;
+        let (tx, commands) = crossbeam_channel::unbounded();
+        std::thread::spawn(move || {
+            let mut buffer = [0; rosc::decoder::MTU];
+            loop {
+                let size = match receiver.recv_from(&mut buffer) {
+                    Ok((size, _)) => size,
+                    Err(err) => {
+                        log::error!("OSC: {err}");
+                        continue;
+                    }
+                };
+                let packet = match rosc::decoder::decode_udp(&buffer[..size]) {
+                    Ok((_, packet)) => packet,
+                    Err(err) => {
+                        log::warn!("Malformed OSC packet: {err}");
+                        continue;
+                    }
+                };
+                let mut messages = vec![];
+                collect_messages(packet, &mut messages);
+                for message in messages {
+                    match OscCommand::parse(&message) {
+                        Ok(command) => {
+                            if tx.send(command).is_err() {
+                                return;
+                            }
+                        }
+                        Err(err) => log::warn!("{err}"),
+                    }
+                }
+            }
+        });
+        log::info!("Listening to OSC on port {port}");
+
+        Ok(Self {
+            socket,
+            commands,
+            feedback,
+            last_feedback: Instant::now(),
+            sent: vec![],
+        })
+    }
+
+    /// Commands received since the last call.
+    pub fn poll(&self) -> impl Iterator<Item = OscCommand> + '_ {
+        self.commands.try_iter()
+    }
+
+    /// Sends the time, the pause state and the parameters that changed since the last time,
+    /// at most every [`FEEDBACK_INTERVAL`].
+    pub fn send_feedback<'a>(
+        &mut self,
+        time: f32,
+        pause: bool,
+        params: impl Iterator<Item = (&'a str, f32)>,
+    ) -> Result<()> {
+        let Some(feedback) = self.feedback else {
+            return Ok(());
+        };
+        if self.last_feedback.elapsed() < FEEDBACK_INTERVAL {
+            return Ok(());
+        }
+        self.last_feedback = Instant::now();
+
+        let message = |addr: String, arg| {
+            OscPacket::Message(OscMessage {
+                addr,
+                args: vec![arg],
+            })
+        };
+        let mut content = vec![
+            message(TIME_ADDRESS.into(), OscType::Float(time)),
+            message(PAUSE_ADDRESS.into(), OscType::Int(pause as i32)),
+        ];
+        for (name, value) in params {
+            match self.sent.iter_mut().find(|(sent, _)| sent == name) {
+                Some((_, sent)) if *sent == value => continue,
+                Some((_, sent)) => *sent = value,
+                None => self.sent.push((name.to_string(), value)),
+            }
+            content.push(message(
+                format!("{PARAM_ADDRESS}{name}"),
+                OscType::Float(value),
+            ));
+        }
+
+        let bundle = OscPacket::Bundle(OscBundle {
+            timetag: OscTime::from((0, 1)),
+            content,
+        });
+        self.socket
+            .send_to(&rosc::encoder::encode(&bundle)?, feedback)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use ash::vk;
+
+use crate::{Device, DeviceBuffer, FrameGuard};
+
+/// Parameters that fit into the buffer behind `pc.params`.
+pub const MAX_PARAMS: usize = 256;
+/// Share of a parameter's range one key press moves it by.
+const NUDGE_STEP: f32 = 0.05;
+
+/// Parameter declared by a shader with `#pragma pilka param warp 0..4 default 1.2`,
+/// parameters made up by MIDI or OSC span 0..1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamDesc {
+    pub name: String,
+    pub range: (f32, f32),
+    pub default: f32,
+}
+
+impl ParamDesc {
+    pub fn new(name: &str, default: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            range: (0., 1.),
+            default,
+        }
+    }
+}
+
+/// Named floats shaders read with `pc.params.values[PARAM_<NAME>]`.
+/// A name keeps its index and value until the application exits, shader reloads included.
+pub struct Params {
+    descs: Vec<ParamDesc>,
+    values: Vec<f32>,
+    /// Parameter moved by the keys.
+    pub selected: Option<usize>,
+    pub buffer: DeviceBuffer,
+}
+
+impl Params {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let buffer = device.create_device_buffer(
+            (MAX_PARAMS * size_of::<f32>()) as _,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+        device.name_object(buffer.buffer, "Params Buffer");
+        Ok(Self {
+            descs: vec![],
+            values: vec![],
+            selected: None,
+            buffer,
+        })
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.descs.iter().position(|desc| desc.name == name)
+    }
+
+    /// Index of `name`, a new name starts at `value`. The flag is set for new names,
+    /// their constant has to be defined before shaders can use them.
+    pub fn register(&mut self, name: &str, value: f32) -> Result<(usize, bool)> {
+        match self.index(name) {
+            Some(idx) => Ok((idx, false)),
+            None => self.push(ParamDesc::new(name, value)),
+        }
+    }
+
+    /// Like [`Params::register`], a declaration updates the range and default of a known
+    /// parameter but keeps its value.
+    pub fn declare(&mut self, desc: &ParamDesc) -> Result<(usize, bool)> {
+        match self.index(&desc.name) {
+            Some(idx) => {
+                self.descs[idx] = desc.clone();
+                Ok((idx, false))
+            }
+            None => self.push(desc.clone()),
+        }
+    }
+
+    fn push(&mut self, desc: ParamDesc) -> Result<(usize, bool)> {
+        if !is_param_name(&desc.name) {
+            bail!("`{}` is not a valid parameter name", desc.name);
+        }
+        if self.descs.len() == MAX_PARAMS {
+            bail!(
+                "No room for `{}`, there are {MAX_PARAMS} parameters already",
+                desc.name
+            );
+        }
+        self.values.push(desc.default);
+        self.descs.push(desc);
+        Ok((self.descs.len() - 1, true))
+    }
+
+    pub fn len(&self) -> usize {
+        self.descs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descs.is_empty()
+    }
+
+    pub fn desc(&self, idx: usize) -> &ParamDesc {
+        &self.descs[idx]
+    }
+
+    pub fn get(&self, idx: usize) -> f32 {
+        self.values[idx]
+    }
+
+    pub fn set(&mut self, idx: usize, value: f32) {
+        self.values[idx] = value;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.descs
+            .iter()
+            .map(|desc| desc.name.as_str())
+            .zip(self.values.iter().copied())
+    }
+
+    /// Moves the selection by `step` parameters, wrapping around.
+    pub fn select(&mut self, step: isize) -> Option<usize> {
+        if self.descs.is_empty() {
+            return None;
+        }
+        let len = self.descs.len() as isize;
+        let idx = match self.selected {
+            Some(idx) => (idx as isize + step).rem_euclid(len),
+            None if step < 0 => len - 1,
+            None => 0,
+        } as usize;
+        self.selected = Some(idx);
+        self.selected
+    }
+
+    /// Moves the selected parameter by `steps` key presses, clamped to its range.
+    pub fn nudge(&mut self, steps: f32) -> Option<usize> {
+        let idx = self.selected?;
+        let (min, max) = self.descs[idx].range;
+        let value = self.values[idx] + (max - min) * NUDGE_STEP * steps;
+        self.values[idx] = value.clamp(min.min(max), min.max(max));
+        Some(idx)
+    }
+
+    /// Returns the selected parameter to its default.
+    pub fn reset(&mut self) -> Option<usize> {
+        let idx = self.selected?;
+        self.values[idx] = self.descs[idx].default;
+        Some(idx)
+    }
+
+    /// Copies the values into the buffer ahead of the frame's passes.
+    pub fn record_upload(&self, frame: &FrameGuard) {
+        if !self.values.is_empty() {
+            frame.update_buffer(self.buffer.buffer, bytemuck::cast_slice(&self.values));
+        }
+    }
+}
+
+/// Shader constant of a parameter's index, `warp` becomes `PARAM_WARP`.
+pub fn param_constant_name(name: &str) -> String {
+    format!("PARAM_{}", name.to_uppercase())
+}
+
+/// Parameter names are GLSL identifiers.
+pub fn is_param_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+}
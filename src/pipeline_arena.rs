@@ -1,9 +1,10 @@
 use ahash::{AHashMap, AHashSet};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use either::Either;
 use slotmap::SlotMap;
 use std::{
     path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
 };
 
@@ -12,11 +13,33 @@ use ash::{
     vk::{self},
 };
 
-use crate::{Device, ShaderCompiler, ShaderKind, ShaderSource, Watcher};
+use crate::{
+    dispatch_optimal, shader_compiler::reflect_workgroup_size, Device, Pragma, ShaderCompiler,
+    ShaderKind, ShaderSource, Watcher,
+};
+
+/// How many workgroups a compute pass launches.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    /// One invocation per pixel of the screen.
+    #[default]
+    Screen,
+    /// Total invocation count, e.g. `[len, 1, 1]` for a pass over a buffer.
+    Invocations([u32; 3]),
+    /// Explicit workgroup count.
+    Workgroups([u32; 3]),
+    /// Workgroup count read from the indirect buffer at dispatch time.
+    Indirect,
+}
 
 pub struct ComputePipeline {
     pub layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
+    /// Dispatch set from code, a `dispatch` or `threads` pragma takes precedence over it.
+    pub dispatch: Dispatch,
+    pub workgroup_size: [u32; 3],
+    pragma_dispatch: Option<Dispatch>,
+    pub image_targets: Vec<(usize, vk::Format, bool)>,
     shader_path: PathBuf,
     device: Arc<Device>,
 }
@@ -38,7 +61,8 @@ impl ComputePipeline {
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
     ) -> Result<Self> {
-        let cs_bytes = shader_compiler.compile(&shader_path, shaderc::ShaderKind::Compute)?;
+        let (cs_bytes, pragmas) =
+            shader_compiler.compile(&shader_path, shaderc::ShaderKind::Compute)?;
 
         let pipeline_layout = unsafe {
             device.create_pipeline_layout(
@@ -63,16 +87,45 @@ impl ComputePipeline {
         };
         let pipeline = pipeline.map_err(|(_, err)| err)?[0];
 
-        Ok(Self {
+        let mut pipeline = Self {
             pipeline,
+            dispatch: Dispatch::default(),
+            workgroup_size: reflect_workgroup_size(cs_bytes.as_binary()),
+            pragma_dispatch: None,
+            image_targets: vec![],
             shader_path: shader_path.as_ref().to_path_buf(),
             layout: pipeline_layout,
             device: device.clone(),
-        })
+        };
+        pipeline.apply_pragmas(pragmas);
+
+        Ok(pipeline)
+    }
+
+    fn apply_pragmas(&mut self, pragmas: Vec<Pragma>) {
+        self.pragma_dispatch = None;
+        self.image_targets.clear();
+        for pragma in pragmas {
+            match pragma {
+                Pragma::Dispatch(dispatch) => self.pragma_dispatch = Some(dispatch),
+                Pragma::Target {
+                    image_idx,
+                    format,
+                    mips,
+                } => self.image_targets.push((image_idx, format, mips)),
+                // Declared before the shader got compiled
+                Pragma::Param(_) => {}
+                pragma => log::warn!(
+                    "{}: {pragma:?} has no effect on a compute pipeline",
+                    self.shader_path.display()
+                ),
+            }
+        }
     }
 
     pub fn reload(&mut self, shader_compiler: &ShaderCompiler) -> Result<()> {
-        let cs_bytes = shader_compiler.compile(&self.shader_path, shaderc::ShaderKind::Compute)?;
+        let (cs_bytes, pragmas) =
+            shader_compiler.compile(&self.shader_path, shaderc::ShaderKind::Compute)?;
 
         unsafe { self.device.destroy_pipeline(self.pipeline, None) }
 
@@ -92,14 +145,32 @@ impl ComputePipeline {
         let pipeline = pipeline.map_err(|(_, err)| err)?[0];
 
         self.pipeline = pipeline;
+        self.workgroup_size = reflect_workgroup_size(cs_bytes.as_binary());
+        self.apply_pragmas(pragmas);
 
         Ok(())
     }
+
+    pub fn is_indirect(&self) -> bool {
+        self.pragma_dispatch.unwrap_or(self.dispatch) == Dispatch::Indirect
+    }
+
+    /// Indirect dispatches have no size known on the host and report a single workgroup.
+    pub fn workgroup_count(&self, extent: vk::Extent2D) -> [u32; 3] {
+        let invocations = match self.pragma_dispatch.unwrap_or(self.dispatch) {
+            Dispatch::Workgroups(groups) => return groups,
+            Dispatch::Indirect => return [1; 3],
+            Dispatch::Invocations(invocations) => invocations,
+            Dispatch::Screen => [extent.width, extent.height, 1],
+        };
+        std::array::from_fn(|i| dispatch_optimal(invocations[i], self.workgroup_size[i]))
+    }
 }
 
 pub struct VertexInputDesc {
     pub primitive_topology: vk::PrimitiveTopology,
     pub primitive_restart: bool,
+    pub vertex_count: u32,
 }
 
 impl Default for VertexInputDesc {
@@ -107,6 +178,7 @@ impl Default for VertexInputDesc {
         Self {
             primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             primitive_restart: false,
+            vertex_count: 3,
         }
     }
 }
@@ -141,9 +213,190 @@ pub struct FragmentShaderDesc {
     pub shader_path: PathBuf,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct DepthDesc {
+    pub format: vk::Format,
+    pub compare_op: vk::CompareOp,
+    pub write_enable: bool,
+    pub clear_value: f32,
+}
+
+impl Default for DepthDesc {
+    fn default() -> Self {
+        Self {
+            format: vk::Format::D32_SFLOAT,
+            compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            write_enable: true,
+            clear_value: 1.0,
+        }
+    }
+}
+
+impl DepthDesc {
+    fn depth_stencil_state(
+        desc: Option<&Self>,
+    ) -> vk::PipelineDepthStencilStateCreateInfo<'static> {
+        match desc {
+            Some(desc) => vk::PipelineDepthStencilStateCreateInfo::default()
+                .depth_test_enable(true)
+                .depth_write_enable(desc.write_enable)
+                .depth_compare_op(desc.compare_op)
+                .max_depth_bounds(1.0),
+            None => vk::PipelineDepthStencilStateCreateInfo::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Replace,
+    Additive,
+    Alpha,
+    Premultiplied,
+    Min,
+    Max,
+    Custom {
+        src_color: vk::BlendFactor,
+        dst_color: vk::BlendFactor,
+        color_op: vk::BlendOp,
+        src_alpha: vk::BlendFactor,
+        dst_alpha: vk::BlendFactor,
+        alpha_op: vk::BlendOp,
+    },
+}
+
+impl BlendMode {
+    pub(crate) fn attachment_state(&self) -> vk::PipelineColorBlendAttachmentState {
+        use vk::{BlendFactor as F, BlendOp as Op};
+        let (src_color, dst_color, color_op, src_alpha, dst_alpha, alpha_op) = match *self {
+            BlendMode::Replace => {
+                return vk::PipelineColorBlendAttachmentState::default()
+                    .color_write_mask(vk::ColorComponentFlags::RGBA)
+            }
+            BlendMode::Additive => (F::ONE, F::ONE, Op::ADD, F::ONE, F::ONE, Op::ADD),
+            BlendMode::Alpha => (
+                F::SRC_ALPHA,
+                F::ONE_MINUS_SRC_ALPHA,
+                Op::ADD,
+                F::ONE,
+                F::ONE_MINUS_SRC_ALPHA,
+                Op::ADD,
+            ),
+            BlendMode::Premultiplied => (
+                F::ONE,
+                F::ONE_MINUS_SRC_ALPHA,
+                Op::ADD,
+                F::ONE,
+                F::ONE_MINUS_SRC_ALPHA,
+                Op::ADD,
+            ),
+            BlendMode::Min => (F::ONE, F::ONE, Op::MIN, F::ONE, F::ONE, Op::MIN),
+            BlendMode::Max => (F::ONE, F::ONE, Op::MAX, F::ONE, F::ONE, Op::MAX),
+            BlendMode::Custom {
+                src_color,
+                dst_color,
+                color_op,
+                src_alpha,
+                dst_alpha,
+                alpha_op,
+            } => (
+                src_color, dst_color, color_op, src_alpha, dst_alpha, alpha_op,
+            ),
+        };
+        vk::PipelineColorBlendAttachmentState::default()
+            .blend_enable(true)
+            .src_color_blend_factor(src_color)
+            .dst_color_blend_factor(dst_color)
+            .color_blend_op(color_op)
+            .src_alpha_blend_factor(src_alpha)
+            .dst_alpha_blend_factor(dst_alpha)
+            .alpha_blend_op(alpha_op)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+    }
+}
+
+/// Parses `replace`, `additive`, `alpha`, `premultiplied`, `min`, `max` or
+/// `custom <src> <dst> [op] [<src_alpha> <dst_alpha> [alpha_op]]`,
+/// e.g. `custom one_minus_dst_color one add`.
+impl FromStr for BlendMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.split_whitespace();
+        let mode = match words.next().unwrap_or_default() {
+            "replace" | "opaque" => BlendMode::Replace,
+            "additive" | "add" => BlendMode::Additive,
+            "alpha" => BlendMode::Alpha,
+            "premultiplied" | "premul" => BlendMode::Premultiplied,
+            "min" => BlendMode::Min,
+            "max" => BlendMode::Max,
+            "custom" => {
+                let words: Vec<_> = words.by_ref().collect();
+                let (color, alpha) = match words.len() {
+                    2 | 3 => (&words[..], &words[..]),
+                    4 => (&words[..2], &words[2..]),
+                    5 | 6 => (&words[..3], &words[3..]),
+                    _ => bail!("Custom blend mode expects `<src> <dst> [op] [<src> <dst> [op]]`"),
+                };
+                let op = |words: &[&str]| {
+                    words
+                        .get(2)
+                        .map_or(Ok(vk::BlendOp::ADD), |op| parse_blend_op(op))
+                };
+                BlendMode::Custom {
+                    src_color: parse_blend_factor(color[0])?,
+                    dst_color: parse_blend_factor(color[1])?,
+                    color_op: op(color)?,
+                    src_alpha: parse_blend_factor(alpha[0])?,
+                    dst_alpha: parse_blend_factor(alpha[1])?,
+                    alpha_op: op(alpha)?,
+                }
+            }
+            mode => bail!("Unknown blend mode: `{mode}`"),
+        };
+        if let Some(word) = words.next() {
+            bail!("Unexpected `{word}` after blend mode");
+        }
+        Ok(mode)
+    }
+}
+
+fn parse_blend_factor(s: &str) -> Result<vk::BlendFactor> {
+    use vk::BlendFactor as F;
+    Ok(match s {
+        "zero" => F::ZERO,
+        "one" => F::ONE,
+        "src_color" => F::SRC_COLOR,
+        "one_minus_src_color" => F::ONE_MINUS_SRC_COLOR,
+        "dst_color" => F::DST_COLOR,
+        "one_minus_dst_color" => F::ONE_MINUS_DST_COLOR,
+        "src_alpha" => F::SRC_ALPHA,
+        "one_minus_src_alpha" => F::ONE_MINUS_SRC_ALPHA,
+        "dst_alpha" => F::DST_ALPHA,
+        "one_minus_dst_alpha" => F::ONE_MINUS_DST_ALPHA,
+        "src_alpha_saturate" => F::SRC_ALPHA_SATURATE,
+        _ => bail!("Unknown blend factor: `{s}`"),
+    })
+}
+
+fn parse_blend_op(s: &str) -> Result<vk::BlendOp> {
+    use vk::BlendOp as Op;
+    Ok(match s {
+        "add" => Op::ADD,
+        "subtract" | "sub" => Op::SUBTRACT,
+        "reverse_subtract" | "rev_sub" => Op::REVERSE_SUBTRACT,
+        "min" => Op::MIN,
+        "max" => Op::MAX,
+        _ => bail!("Unknown blend op: `{s}`"),
+    })
+}
+
 pub struct FragmentOutputDesc {
     pub surface_format: vk::Format,
     pub multisample_state: vk::SampleCountFlags,
+    pub depth: Option<DepthDesc>,
+    pub blend: BlendMode,
 }
 
 impl Default for FragmentOutputDesc {
@@ -151,20 +404,49 @@ impl Default for FragmentOutputDesc {
         Self {
             surface_format: vk::Format::B8G8R8A8_SRGB,
             multisample_state: vk::SampleCountFlags::TYPE_1,
+            depth: None,
+            blend: BlendMode::Replace,
         }
     }
 }
 
+impl FragmentOutputDesc {
+    fn depth_format(&self) -> vk::Format {
+        self.depth
+            .map_or(vk::Format::UNDEFINED, |depth| depth.format)
+    }
+}
+
 pub struct RenderPipeline {
     pub layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
+    pub vertex_count: u32,
+    /// Draw arguments are read from the indirect buffer instead of `vertex_count`.
+    pub indirect: bool,
+    pub image_targets: Vec<(usize, vk::Format, bool)>,
     vertex_input_lib: vk::Pipeline,
     vertex_shader_lib: vk::Pipeline,
     fragment_shader_lib: vk::Pipeline,
     fragment_output_lib: vk::Pipeline,
+    surface_format: vk::Format,
+    depth: Option<DepthDesc>,
+    samples: vk::SampleCountFlags,
+    blend: BlendMode,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    base: PipelineBase,
+    vertex_pragmas: Vec<Pragma>,
+    fragment_pragmas: Vec<Pragma>,
     device: Arc<Device>,
 }
 
+/// Settings from the pipeline descriptors that shader pragmas are able to override.
+struct PipelineBase {
+    blend: BlendMode,
+    topology: vk::PrimitiveTopology,
+    vertex_count: u32,
+}
+
 impl RenderPipeline {
     pub fn new(
         device: &Arc<Device>,
@@ -176,9 +458,9 @@ impl RenderPipeline {
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
     ) -> Result<Self> {
-        let vs_bytes = shader_compiler
+        let (vs_bytes, vertex_pragmas) = shader_compiler
             .compile(&vertex_shader_desc.shader_path, shaderc::ShaderKind::Vertex)?;
-        let fs_bytes = shader_compiler.compile(
+        let (fs_bytes, fragment_pragmas) = shader_compiler.compile(
             &fragment_shader_desc.shader_path,
             shaderc::ShaderKind::Fragment,
         )?;
@@ -193,17 +475,11 @@ impl RenderPipeline {
         };
 
         use vk::GraphicsPipelineLibraryFlagsEXT as GPF;
-        let vertex_input_lib = {
-            let input_ass = vk::PipelineInputAssemblyStateCreateInfo::default()
-                .topology(vertex_input_desc.primitive_topology)
-                .primitive_restart_enable(vertex_input_desc.primitive_restart);
-            let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
-
-            create_library(device, GPF::VERTEX_INPUT_INTERFACE, |desc| {
-                desc.vertex_input_state(&vertex_input)
-                    .input_assembly_state(&input_ass)
-            })?
-        };
+        let vertex_input_lib = Self::create_vertex_input_lib(
+            device,
+            vertex_input_desc.primitive_topology,
+            vertex_input_desc.primitive_restart,
+        )?;
 
         let vertex_shader_lib = {
             let mut shader_module =
@@ -240,47 +516,184 @@ impl RenderPipeline {
                 .name(c"main")
                 .push_next(&mut shader_module);
 
-            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
+            let depth_stencil_state =
+                DepthDesc::depth_stencil_state(fragment_output_desc.depth.as_ref());
+            let color_attachment_formats = [fragment_output_desc.surface_format];
+            let mut dyn_render = vk::PipelineRenderingCreateInfo::default()
+                .color_attachment_formats(&color_attachment_formats)
+                .depth_attachment_format(fragment_output_desc.depth_format());
 
             create_library(device, GPF::FRAGMENT_SHADER, |desc| {
                 desc.layout(pipeline_layout)
                     .stages(std::slice::from_ref(&shader_stage))
                     .depth_stencil_state(&depth_stencil_state)
-            })?
-        };
-
-        let fragment_output_lib = {
-            let color_attachment_formats = [fragment_output_desc.surface_format];
-            let mut dyn_render = vk::PipelineRenderingCreateInfo::default()
-                .color_attachment_formats(&color_attachment_formats);
-
-            let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
-                .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-
-            create_library(device, GPF::FRAGMENT_OUTPUT_INTERFACE, |desc| {
-                desc.multisample_state(&multisample_state)
                     .push_next(&mut dyn_render)
             })?
         };
 
-        let pipeline = Self::link_libraries(
+        let fragment_output_lib = Self::create_fragment_output_lib(
             device,
-            &pipeline_layout,
-            &vertex_input_lib,
-            &vertex_shader_lib,
-            &fragment_shader_lib,
-            &fragment_output_lib,
+            fragment_output_desc.surface_format,
+            fragment_output_desc.depth.as_ref(),
+            fragment_output_desc.multisample_state,
+            fragment_output_desc.blend,
         )?;
 
-        Ok(Self {
+        let mut pipeline = Self {
             device: device.clone(),
             layout: pipeline_layout,
-            pipeline,
+            pipeline: vk::Pipeline::null(),
+            vertex_count: vertex_input_desc.vertex_count,
+            indirect: false,
+            image_targets: vec![],
             vertex_input_lib,
             vertex_shader_lib,
             fragment_shader_lib,
             fragment_output_lib,
-        })
+            surface_format: fragment_output_desc.surface_format,
+            depth: fragment_output_desc.depth,
+            samples: fragment_output_desc.multisample_state,
+            blend: fragment_output_desc.blend,
+            topology: vertex_input_desc.primitive_topology,
+            primitive_restart: vertex_input_desc.primitive_restart,
+            base: PipelineBase {
+                blend: fragment_output_desc.blend,
+                topology: vertex_input_desc.primitive_topology,
+                vertex_count: vertex_input_desc.vertex_count,
+            },
+            vertex_pragmas,
+            fragment_pragmas,
+        };
+        pipeline.apply_pragmas()?;
+        pipeline.link()?;
+
+        Ok(pipeline)
+    }
+
+    /// Overrides descriptor settings with the pragmas of both stages and rebuilds
+    /// the libraries those settings belong to.
+    fn apply_pragmas(&mut self) -> Result<()> {
+        let mut blend = self.base.blend;
+        let mut topology = self.base.topology;
+        self.vertex_count = self.base.vertex_count;
+        self.indirect = false;
+        self.image_targets.clear();
+        for pragma in self.vertex_pragmas.iter().chain(&self.fragment_pragmas) {
+            match *pragma {
+                Pragma::Blend(mode) => blend = mode,
+                Pragma::Vertices {
+                    count,
+                    topology: pragma_topology,
+                } => {
+                    self.vertex_count = count;
+                    topology = pragma_topology.unwrap_or(topology);
+                }
+                Pragma::IndirectDraw => self.indirect = true,
+                Pragma::Target {
+                    image_idx,
+                    format,
+                    mips,
+                } => self.image_targets.push((image_idx, format, mips)),
+                Pragma::Param(_) => {}
+                ref pragma => log::warn!("{pragma:?} has no effect on a render pipeline"),
+            }
+        }
+
+        if topology != self.topology {
+            self.topology = topology;
+            unsafe { self.device.destroy_pipeline(self.vertex_input_lib, None) };
+            self.vertex_input_lib =
+                Self::create_vertex_input_lib(&self.device, topology, self.primitive_restart)?;
+        }
+        if blend != self.blend {
+            self.blend = blend;
+            self.reload_fragment_output_lib()?;
+        }
+
+        Ok(())
+    }
+
+    fn create_vertex_input_lib(
+        device: &ash::Device,
+        topology: vk::PrimitiveTopology,
+        primitive_restart: bool,
+    ) -> VkResult<vk::Pipeline> {
+        let input_ass = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(topology)
+            .primitive_restart_enable(primitive_restart);
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+
+        create_library(
+            device,
+            vk::GraphicsPipelineLibraryFlagsEXT::VERTEX_INPUT_INTERFACE,
+            |desc| {
+                desc.vertex_input_state(&vertex_input)
+                    .input_assembly_state(&input_ass)
+            },
+        )
+    }
+
+    fn create_fragment_output_lib(
+        device: &ash::Device,
+        surface_format: vk::Format,
+        depth: Option<&DepthDesc>,
+        samples: vk::SampleCountFlags,
+        blend: BlendMode,
+    ) -> VkResult<vk::Pipeline> {
+        let color_attachment_formats = [surface_format];
+        let mut dyn_render = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&color_attachment_formats)
+            .depth_attachment_format(depth.map_or(vk::Format::UNDEFINED, |depth| depth.format));
+
+        let multisample_state =
+            vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(samples);
+
+        let color_blend_attachments = [blend.attachment_state()];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+
+        create_library(
+            device,
+            vk::GraphicsPipelineLibraryFlagsEXT::FRAGMENT_OUTPUT_INTERFACE,
+            |desc| {
+                desc.multisample_state(&multisample_state)
+                    .color_blend_state(&color_blend_state)
+                    .push_next(&mut dyn_render)
+            },
+        )
+    }
+
+    pub fn reload_fragment_output_lib(&mut self) -> Result<()> {
+        unsafe { self.device.destroy_pipeline(self.fragment_output_lib, None) };
+        self.fragment_output_lib = Self::create_fragment_output_lib(
+            &self.device,
+            self.surface_format,
+            self.depth.as_ref(),
+            self.samples,
+            self.blend,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    pub fn set_samples(&mut self, samples: vk::SampleCountFlags) -> Result<()> {
+        self.samples = samples;
+        self.reload_fragment_output_lib()
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend
+    }
+
+    /// Only the fragment output library gets rebuilt, the pipeline still needs to be relinked.
+    /// A `blend` pragma in the shaders takes precedence over this mode.
+    pub fn set_blend_mode(&mut self, blend: BlendMode) -> Result<()> {
+        self.base.blend = blend;
+        self.apply_pragmas()
     }
 
     pub fn reload_vertex_lib(
@@ -288,7 +701,8 @@ impl RenderPipeline {
         shader_compiler: &ShaderCompiler,
         shader_path: impl AsRef<Path>,
     ) -> Result<()> {
-        let vs_bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Vertex)?;
+        let (vs_bytes, pragmas) =
+            shader_compiler.compile(shader_path, shaderc::ShaderKind::Vertex)?;
 
         unsafe { self.device.destroy_pipeline(self.vertex_shader_lib, None) };
 
@@ -320,8 +734,9 @@ impl RenderPipeline {
         )?;
 
         self.vertex_shader_lib = vertex_shader_lib;
+        self.vertex_pragmas = pragmas;
 
-        Ok(())
+        self.apply_pragmas()
     }
 
     pub fn reload_fragment_lib(
@@ -329,7 +744,8 @@ impl RenderPipeline {
         shader_compiler: &ShaderCompiler,
         shader_path: impl AsRef<Path>,
     ) -> Result<()> {
-        let fs_bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Fragment)?;
+        let (fs_bytes, pragmas) =
+            shader_compiler.compile(shader_path, shaderc::ShaderKind::Fragment)?;
 
         unsafe { self.device.destroy_pipeline(self.fragment_shader_lib, None) };
 
@@ -339,7 +755,14 @@ impl RenderPipeline {
             .name(c"main")
             .push_next(&mut shader_module);
 
-        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
+        let depth_stencil_state = DepthDesc::depth_stencil_state(self.depth.as_ref());
+        let color_attachment_formats = [self.surface_format];
+        let mut dyn_render = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&color_attachment_formats)
+            .depth_attachment_format(
+                self.depth
+                    .map_or(vk::Format::UNDEFINED, |depth| depth.format),
+            );
 
         let fragment_shader_lib = create_library(
             &self.device,
@@ -348,12 +771,14 @@ impl RenderPipeline {
                 desc.layout(self.layout)
                     .stages(std::slice::from_ref(&shader_stage))
                     .depth_stencil_state(&depth_stencil_state)
+                    .push_next(&mut dyn_render)
             },
         )?;
 
         self.fragment_shader_lib = fragment_shader_lib;
+        self.fragment_pragmas = pragmas;
 
-        Ok(())
+        self.apply_pragmas()
     }
 
     pub fn link(&mut self) -> Result<()> {
@@ -0,0 +1,391 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+
+use crate::{
+    params::is_param_name, BlendMode, Dispatch, ParamDesc, GENERIC_IMAGE1_IDX, GENERIC_IMAGE2_IDX,
+    PREV_FRAME_IMAGE_IDX,
+};
+
+const PRAGMA_PREFIX: &str = "pilka";
+
+/// Pipeline settings declared next to the shader code with `#pragma pilka <name> <args..>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pragma {
+    /// `#pragma pilka dispatch 8 8 [1]`: number of workgroups of a compute pass,
+    /// `#pragma pilka threads 65536`: number of invocations, `dispatch screen` to reset,
+    /// `dispatch indirect` to read the workgroup count from `pc.indirect`.
+    Dispatch(Dispatch),
+    /// `#pragma pilka indirect`: read the draw arguments from `pc.indirect`.
+    IndirectDraw,
+    /// `#pragma pilka blend additive`: see [`BlendMode`] for the syntax.
+    Blend(BlendMode),
+    /// `#pragma pilka vertices 100000 [points]`: vertex count and topology of a draw.
+    Vertices {
+        count: u32,
+        topology: Option<vk::PrimitiveTopology>,
+    },
+    /// `#pragma pilka target GENERIC_TEX1 rgba16f`: format of a screen-sized image,
+    /// `target PREV_TEX rgba16f mips` also keeps a mip chain of the previous frame.
+    Target {
+        image_idx: usize,
+        format: vk::Format,
+        mips: bool,
+    },
+    /// `#pragma pilka param warp 0..4 default 1.2`: a parameter in `pc.params`,
+    /// the range defaults to 0..1 and the default value to the start of the range.
+    Param(ParamDesc),
+}
+
+impl Pragma {
+    fn parse(name: &str, args: &[&str]) -> Result<Self> {
+        let pragma = match name {
+            "dispatch" if args == ["screen"] => Pragma::Dispatch(Dispatch::Screen),
+            "dispatch" if args == ["indirect"] => Pragma::Dispatch(Dispatch::Indirect),
+            "dispatch" => Pragma::Dispatch(Dispatch::Workgroups(parse_dimensions(name, args)?)),
+            "threads" => Pragma::Dispatch(Dispatch::Invocations(parse_dimensions(name, args)?)),
+            "indirect" if args.is_empty() => Pragma::IndirectDraw,
+            "indirect" => bail!("`indirect` takes no arguments"),
+            "blend" => Pragma::Blend(args.join(" ").parse()?),
+            "vertices" => {
+                let (count, topology) = match args {
+                    [count] => (count, None),
+                    [count, topology] => (count, Some(parse_topology(topology)?)),
+                    _ => bail!("Expected `vertices <count> [topology]`"),
+                };
+                let count = count
+                    .parse()
+                    .with_context(|| format!("Invalid vertex count `{count}`"))?;
+                Pragma::Vertices { count, topology }
+            }
+            "target" => {
+                let (image, format, mips) = match args {
+                    [image, format] => (image, format, false),
+                    [image, format, "mips"] => (image, format, true),
+                    _ => bail!("Expected `target <image> <format> [mips]`"),
+                };
+                let image_idx = parse_target_image(image)?;
+                if mips && image_idx != PREV_FRAME_IMAGE_IDX {
+                    bail!("Only PREV_TEX is written every frame and can have mips");
+                }
+                Pragma::Target {
+                    image_idx,
+                    format: parse_format(format)?,
+                    mips,
+                }
+            }
+            "param" => Pragma::Param(parse_param(args)?),
+            _ => bail!("Unknown pragma `{name}`"),
+        };
+        Ok(pragma)
+    }
+}
+
+/// Collects every `#pragma pilka` line of the source, malformed and unknown ones are
+/// reported as warnings and skipped. Only the file itself is scanned, pragmas in
+/// `#include`d files are ignored.
+pub fn parse_pragmas(source: &str, file_name: &str) -> Vec<Pragma> {
+    let mut pragmas = vec![];
+    for (line_idx, line) in source.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let (Some("#pragma"), Some(PRAGMA_PREFIX)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Some(name) = words.next() else {
+            log::warn!("{file_name}:{}: Empty pilka pragma", line_idx + 1);
+            continue;
+        };
+        let args: Vec<_> = words.take_while(|word| !word.starts_with("//")).collect();
+        match Pragma::parse(name, &args) {
+            Ok(pragma) => pragmas.push(pragma),
+            Err(err) => log::warn!("{file_name}:{}: {err:#}", line_idx + 1),
+        }
+    }
+    pragmas
+}
+
+/// Parameters declared by the `param` pragmas of a shader file, shaders can only use
+/// their constants once they are declared, so this runs ahead of compilation.
+pub fn declared_params(path: &Path) -> Result<Vec<ParamDesc>> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let params = parse_pragmas(&source, file_name)
+        .into_iter()
+        .filter_map(|pragma| match pragma {
+            Pragma::Param(desc) => Some(desc),
+            _ => None,
+        })
+        .collect();
+    Ok(params)
+}
+
+fn parse_param(args: &[&str]) -> Result<ParamDesc> {
+    const USAGE: &str = "Expected `param <name> [min..max] [default <value>]`";
+    let (name, args) = args.split_first().context(USAGE)?;
+    if !is_param_name(name) {
+        bail!("`{name}` is not a valid parameter name");
+    }
+    let parse_value = |s: &str| -> Result<f32> {
+        s.parse()
+            .ok()
+            .filter(|value: &f32| value.is_finite())
+            .with_context(|| format!("Invalid value `{s}`"))
+    };
+    let (range, args) = match args.split_first() {
+        Some((range, args)) if range.contains("..") => {
+            let (min, max) = range.split_once("..").unwrap();
+            ((parse_value(min)?, parse_value(max)?), args)
+        }
+        _ => ((0., 1.), args),
+    };
+    let default = match args {
+        [] => range.0,
+        ["default", value] => parse_value(value)?,
+        _ => bail!(USAGE),
+    };
+    Ok(ParamDesc {
+        name: name.to_string(),
+        range,
+        default,
+    })
+}
+
+fn parse_dimensions(name: &str, args: &[&str]) -> Result<[u32; 3]> {
+    if args.is_empty() || args.len() > 3 {
+        bail!("Expected `{name} <x> [y] [z]`");
+    }
+    let mut dims = [1; 3];
+    for (dim, arg) in dims.iter_mut().zip(args) {
+        *dim = arg
+            .parse()
+            .with_context(|| format!("Invalid dimension `{arg}`"))?;
+        if *dim == 0 {
+            bail!("Dimensions of `{name}` must be greater than zero");
+        }
+    }
+    Ok(dims)
+}
+
+fn parse_topology(s: &str) -> Result<vk::PrimitiveTopology> {
+    use vk::PrimitiveTopology as T;
+    Ok(match s {
+        "points" => T::POINT_LIST,
+        "lines" => T::LINE_LIST,
+        "line_strip" => T::LINE_STRIP,
+        "triangles" => T::TRIANGLE_LIST,
+        "triangle_strip" => T::TRIANGLE_STRIP,
+        "triangle_fan" => T::TRIANGLE_FAN,
+        _ => bail!("Unknown topology `{s}`"),
+    })
+}
+
+fn parse_target_image(s: &str) -> Result<usize> {
+    Ok(match s {
+        "PREV_TEX" => PREV_FRAME_IMAGE_IDX,
+        "GENERIC_TEX1" => GENERIC_IMAGE1_IDX,
+        "GENERIC_TEX2" => GENERIC_IMAGE2_IDX,
+        _ => bail!("Unknown target image `{s}`"),
+    })
+}
+
+fn parse_format(s: &str) -> Result<vk::Format> {
+    use vk::Format as F;
+    Ok(match s {
+        "r8" => F::R8_UNORM,
+        "rg8" => F::R8G8_UNORM,
+        "rgba8" => F::R8G8B8A8_UNORM,
+        "rgba8_srgb" | "srgb" => F::R8G8B8A8_SRGB,
+        "rgba8_snorm" => F::R8G8B8A8_SNORM,
+        "r16f" => F::R16_SFLOAT,
+        "rg16f" => F::R16G16_SFLOAT,
+        "rgba16f" => F::R16G16B16A16_SFLOAT,
+        "r32f" => F::R32_SFLOAT,
+        "rg32f" => F::R32G32_SFLOAT,
+        "rgba32f" => F::R32G32B32A32_SFLOAT,
+        "r11g11b10f" => F::B10G11R11_UFLOAT_PACK32,
+        "rgb10a2" => F::A2B10G10R10_UNORM_PACK32,
+        _ => bail!("Unknown format `{s}`"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Pragma> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        Pragma::parse(words[0], &words[1..])
+    }
+
+    #[test]
+    fn valid_pragmas() {
+        let param = |range, default| {
+            Pragma::Param(ParamDesc {
+                name: "warp".to_string(),
+                range,
+                default,
+            })
+        };
+        let target = |image_idx, format, mips| Pragma::Target {
+            image_idx,
+            format,
+            mips,
+        };
+        let cases = [
+            ("dispatch screen", Pragma::Dispatch(Dispatch::Screen)),
+            ("dispatch indirect", Pragma::Dispatch(Dispatch::Indirect)),
+            (
+                "dispatch 8 8",
+                Pragma::Dispatch(Dispatch::Workgroups([8, 8, 1])),
+            ),
+            (
+                "threads 65536",
+                Pragma::Dispatch(Dispatch::Invocations([65536, 1, 1])),
+            ),
+            ("indirect", Pragma::IndirectDraw),
+            ("blend additive", Pragma::Blend(BlendMode::Additive)),
+            (
+                "vertices 100000",
+                Pragma::Vertices {
+                    count: 100000,
+                    topology: None,
+                },
+            ),
+            (
+                "vertices 4 triangle_strip",
+                Pragma::Vertices {
+                    count: 4,
+                    topology: Some(vk::PrimitiveTopology::TRIANGLE_STRIP),
+                },
+            ),
+            (
+                "target GENERIC_TEX1 rgba16f",
+                target(GENERIC_IMAGE1_IDX, vk::Format::R16G16B16A16_SFLOAT, false),
+            ),
+            (
+                "target PREV_TEX r11g11b10f mips",
+                target(
+                    PREV_FRAME_IMAGE_IDX,
+                    vk::Format::B10G11R11_UFLOAT_PACK32,
+                    true,
+                ),
+            ),
+            ("param warp", param((0., 1.), 0.)),
+            ("param warp -1..4", param((-1., 4.), -1.)),
+            ("param warp 0..4 default 1.2", param((0., 4.), 1.2)),
+            ("param warp default 0.5", param((0., 1.), 0.5)),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(parse(line).unwrap(), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn malformed_pragmas() {
+        for line in [
+            "dispatch",
+            "dispatch 0 8",
+            "dispatch 1 2 3 4",
+            "dispatch x",
+            "threads",
+            "indirect 1",
+            "blend",
+            "blend multiply",
+            "vertices",
+            "vertices many",
+            "vertices 3 quads",
+            "target GENERIC_TEX1",
+            "target GENERIC_TEX1 rgba16f mips",
+            "target SCREEN rgba8",
+            "target PREV_TEX rgb9",
+            "param",
+            "param 2warp",
+            "param warp 0..x",
+            "param warp 0..1 default",
+            "param warp default inf",
+            "param warp 0..1 1",
+            "shading rate",
+        ] {
+            assert!(parse(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn skips_other_lines() {
+        let source = "#version 460
+#pragma optimize(on)
+#pragma pilka dispatch 8 8 // one workgroup per tile
+#pragma pilka
+#pragma pilka blend multiply
+  #pragma  pilka indirect
+void main() {}
+";
+        assert_eq!(
+            parse_pragmas(source, "test.comp"),
+            [
+                Pragma::Dispatch(Dispatch::Workgroups([8, 8, 1])),
+                Pragma::IndirectDraw,
+            ]
+        );
+    }
+
+    #[test]
+    fn blend_modes() {
+        use vk::{BlendFactor as F, BlendOp as Op};
+        let custom = |color: (F, F, Op), alpha: (F, F, Op)| BlendMode::Custom {
+            src_color: color.0,
+            dst_color: color.1,
+            color_op: color.2,
+            src_alpha: alpha.0,
+            dst_alpha: alpha.1,
+            alpha_op: alpha.2,
+        };
+        let cases = [
+            ("replace", BlendMode::Replace),
+            ("opaque", BlendMode::Replace),
+            ("add", BlendMode::Additive),
+            ("alpha", BlendMode::Alpha),
+            ("premul", BlendMode::Premultiplied),
+            ("min", BlendMode::Min),
+            ("max", BlendMode::Max),
+            (
+                "custom one_minus_dst_color one",
+                custom(
+                    (F::ONE_MINUS_DST_COLOR, F::ONE, Op::ADD),
+                    (F::ONE_MINUS_DST_COLOR, F::ONE, Op::ADD),
+                ),
+            ),
+            (
+                "custom one one zero one",
+                custom((F::ONE, F::ONE, Op::ADD), (F::ZERO, F::ONE, Op::ADD)),
+            ),
+            (
+                "custom src_alpha one rev_sub zero one max",
+                custom(
+                    (F::SRC_ALPHA, F::ONE, Op::REVERSE_SUBTRACT),
+                    (F::ZERO, F::ONE, Op::MAX),
+                ),
+            ),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(s.parse::<BlendMode>().unwrap(), expected, "{s}");
+        }
+
+        for s in [
+            "",
+            "multiply",
+            "additive one",
+            "custom one",
+            "custom one one add one one add one",
+            "custom one two",
+            "custom one one plus",
+        ] {
+            assert!(s.parse::<BlendMode>().is_err(), "{s}");
+        }
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::{Context, Result};
 use std::{
     fs::File,
     io::{BufWriter, Write},
-    path::Path,
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
     thread::JoinHandle,
     time::Instant,
@@ -11,8 +11,18 @@ use std::{
 use crate::{create_folder, ImageDimensions, ManagedImage, SCREENSHOT_FOLDER, VIDEO_FOLDER};
 use crossbeam_channel::{Receiver, Sender};
 
+/// Frame rate of recorded videos, the timeline advances by one frame of it per captured frame.
+pub const RECORD_FRAME_RATE: u32 = 60;
+
+/// Audio muxed into a recording, `offset` is the timeline time the recording starts at.
+#[derive(Debug, Clone)]
+pub struct RecordAudio {
+    pub path: PathBuf,
+    pub offset: f32,
+}
+
 pub enum RecordEvent {
-    Start(ImageDimensions),
+    Start(ImageDimensions, Option<RecordAudio>),
     Record(ManagedImage),
     Finish,
     Screenshot(ManagedImage),
@@ -71,9 +81,9 @@ impl Recorder {
             .context("Failed to send screenshot");
     }
 
-    pub fn start(&mut self, dims: ImageDimensions) {
+    pub fn start(&mut self, dims: ImageDimensions, audio: Option<RecordAudio>) {
         self.is_active = true;
-        self.send(RecordEvent::Start(dims));
+        self.send(RecordEvent::Start(dims, audio));
     }
 
     pub fn record(&self, image: ManagedImage) {
@@ -101,14 +111,22 @@ struct RecorderThread {
     process: Child,
 }
 
-fn new_ffmpeg_command(image_dimensions: ImageDimensions, filename: &str) -> Result<RecorderThread> {
+fn new_ffmpeg_command(
+    image_dimensions: ImageDimensions,
+    audio: Option<&RecordAudio>,
+    filename: &str,
+) -> Result<RecorderThread> {
+    let framerate = RECORD_FRAME_RATE.to_string();
     #[rustfmt::skip]
-    let args = [
-        "-framerate", "60",
+    let input_args = [
+        "-framerate", &framerate,
         "-pix_fmt", "rgba",
         "-f", "rawvideo",
         // "-vcodec", "rawvideo",
         "-i", "pipe:",
+    ];
+    #[rustfmt::skip]
+    let args = [
         "-c:v", "libx264",
         "-crf", "23",
         // "-preset", "ultrafast",
@@ -131,6 +149,17 @@ fn new_ffmpeg_command(image_dimensions: ImageDimensions, filename: &str) -> Resu
             "{}x{}",
             image_dimensions.width, image_dimensions.height
         ))
+        .args(input_args);
+    if let Some(audio) = audio {
+        // Starts the track at the recording's time, `-shortest` cuts it with the last frame
+        command
+            .args(["-ss", &format!("{:.3}", audio.offset)])
+            .arg("-i")
+            .arg(&audio.path)
+            .args(["-map", "0:v", "-map", "1:a", "-c:a", "aac", "-b:a", "192k"])
+            .arg("-shortest");
+    }
+    command
         .args(args)
         .arg(filename)
         .stdin(Stdio::piped())
@@ -154,15 +183,21 @@ fn record_thread(rx: Receiver<RecordEvent>) {
 
     while let Ok(event) = rx.recv() {
         match event {
-            RecordEvent::Start(image_dimensions) => {
+            RecordEvent::Start(image_dimensions, audio) => {
                 create_folder(VIDEO_FOLDER).unwrap();
                 let dir_path = Path::new(VIDEO_FOLDER);
                 let filename = dir_path.join(format!(
                     "record-{}.mp4",
                     chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
                 ));
-                recorder =
-                    Some(new_ffmpeg_command(image_dimensions, filename.to_str().unwrap()).unwrap());
+                recorder = Some(
+                    new_ffmpeg_command(
+                        image_dimensions,
+                        audio.as_ref(),
+                        filename.to_str().unwrap(),
+                    )
+                    .unwrap(),
+                );
             }
             RecordEvent::Record(mut frame) => {
                 if let Some(ref mut recorder) = recorder {
@@ -1,9 +1,19 @@
 use std::path::Path;
 
-use crate::{Watcher, SHADER_FOLDER};
+use ahash::AHashMap;
+
+use crate::{pragma::parse_pragmas, Pragma, Watcher, SHADER_FOLDER};
 use anyhow::{Context, Result};
 use shaderc::{CompilationArtifact, IncludeType, ShaderKind};
 
+pub const SHADER_EXTENSIONS: [&str; 4] = ["glsl", "frag", "vert", "comp"];
+
+pub fn is_shader_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SHADER_EXTENSIONS.contains(&ext))
+}
+
 pub struct ShaderCompiler {
     compiler: shaderc::Compiler,
     options: shaderc::CompileOptions<'static>,
@@ -69,14 +79,152 @@ impl ShaderCompiler {
         })
     }
 
-    pub fn compile(&self, path: impl AsRef<Path>, kind: ShaderKind) -> Result<CompilationArtifact> {
+    /// Defines a macro for every shader compiled from now on.
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.options.add_macro_definition(name, Some(value));
+    }
+
+    /// shaderc can't drop a definition, so the macro expands to nothing and
+    /// shaders still using it fail to compile.
+    pub fn undefine(&mut self, name: &str) {
+        self.options.add_macro_definition(name, None);
+    }
+
+    pub fn compile(
+        &self,
+        path: impl AsRef<Path>,
+        kind: ShaderKind,
+    ) -> Result<(CompilationArtifact, Vec<Pragma>)> {
         let source = std::fs::read_to_string(path.as_ref())?;
-        Ok(self.compiler.compile_into_spirv(
+        let file_name = path.as_ref().file_name().and_then(|s| s.to_str()).unwrap();
+        let artifact = self.compiler.compile_into_spirv(
             &source,
             kind,
-            path.as_ref().file_name().and_then(|s| s.to_str()).unwrap(),
+            file_name,
             "main",
             Some(&self.options),
-        )?)
+        )?;
+        let pragmas = parse_pragmas(&source, file_name);
+        Ok((artifact, pragmas))
+    }
+
+    /// Compiles a shader embedded in the application, `name` shows up in errors.
+    pub fn compile_source(
+        &self,
+        source: &str,
+        kind: ShaderKind,
+        name: &str,
+    ) -> Result<CompilationArtifact> {
+        let artifact =
+            self.compiler
+                .compile_into_spirv(source, kind, name, "main", Some(&self.options))?;
+        Ok(artifact)
+    }
+}
+
+/// Reads the `local_size` of a compute shader from its SPIR-V. GLSL defaults every
+/// dimension to 1 when it's not declared.
+pub fn reflect_workgroup_size(spirv: &[u32]) -> [u32; 3] {
+    const OP_CONSTANT: u32 = 43;
+    const OP_SPEC_CONSTANT: u32 = 50;
+    const OP_EXECUTION_MODE: u32 = 16;
+    const OP_EXECUTION_MODE_ID: u32 = 331;
+    const LOCAL_SIZE: u32 = 17;
+    const LOCAL_SIZE_ID: u32 = 38;
+    const HEADER_LEN: usize = 5;
+
+    let mut size = [1; 3];
+    let mut size_ids = None;
+    let mut constants = AHashMap::new();
+
+    let mut words = spirv.get(HEADER_LEN..).unwrap_or_default();
+    while let Some(&first) = words.first() {
+        let (len, opcode) = ((first >> 16) as usize, first & 0xffff);
+        if len == 0 || len > words.len() {
+            break;
+        }
+        let operands = &words[1..len];
+        match (opcode, operands) {
+            (OP_EXECUTION_MODE, &[_, LOCAL_SIZE, x, y, z]) => size = [x, y, z],
+            (OP_EXECUTION_MODE_ID, &[_, LOCAL_SIZE_ID, x, y, z]) => size_ids = Some([x, y, z]),
+            (OP_CONSTANT | OP_SPEC_CONSTANT, &[_, id, value]) => {
+                constants.insert(id, value);
+            }
+            _ => {}
+        }
+        words = &words[len..];
+    }
+
+    if let Some(ids) = size_ids {
+        for (dim, id) in size.iter_mut().zip(ids) {
+            *dim = constants.get(&id).copied().unwrap_or(1);
+        }
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Same targets as the app, SPIR-V 1.6 declares spec constant sizes with `LocalSizeId`.
+    fn compile_compute(source: &str) -> Vec<u32> {
+        let compiler = shaderc::Compiler::new().unwrap();
+        let mut options = shaderc::CompileOptions::new().unwrap();
+        options.set_target_env(
+            shaderc::TargetEnv::Vulkan,
+            shaderc::EnvVersion::Vulkan1_3 as u32,
+        );
+        options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+        options.set_target_spirv(shaderc::SpirvVersion::V1_6);
+        compiler
+            .compile_into_spirv(
+                source,
+                ShaderKind::Compute,
+                "test.comp",
+                "main",
+                Some(&options),
+            )
+            .unwrap()
+            .as_binary()
+            .to_vec()
+    }
+
+    #[test]
+    fn workgroup_size() {
+        let cases = [
+            ("", [1, 1, 1]),
+            ("layout(local_size_x = 64) in;", [64, 1, 1]),
+            (
+                "layout(local_size_x = 8, local_size_y = 4, local_size_z = 2) in;",
+                [8, 4, 2],
+            ),
+            (
+                concat!(
+                    "layout(local_size_x = 16, local_size_y = 8, ",
+                    "local_size_x_id = 0, local_size_y_id = 1) in;",
+                ),
+                [16, 8, 1],
+            ),
+            (
+                "layout(local_size_x = 32, local_size_x_id = 3, local_size_y = 2) in;",
+                [32, 2, 1],
+            ),
+        ];
+        for (layout, expected) in cases {
+            let source = format!("#version 460\n{layout}\nvoid main() {{}}\n");
+            let spirv = compile_compute(&source);
+            assert_eq!(reflect_workgroup_size(&spirv), expected, "{layout}");
+        }
+    }
+
+    #[test]
+    fn workgroup_size_of_broken_spirv() {
+        assert_eq!(reflect_workgroup_size(&[]), [1, 1, 1]);
+        // An instruction claiming more words than are left
+        assert_eq!(
+            reflect_workgroup_size(&[0, 0, 0, 0, 0, (6 << 16) | 16]),
+            [1, 1, 1]
+        );
     }
 }
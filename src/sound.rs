@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::{AudioClip, ComputePipeline, Device};
+
+pub const SOUND_SAMPLE_RATE: u32 = 44100;
+/// Seconds rendered by the sound shader, recordings render at least their length.
+pub const SOUND_DURATION: f32 = 60.;
+
+/// Push constant of the sound shader, `samples` points to interleaved stereo frames.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SoundConstant {
+    pub samples: u64,
+    pub sample_rate: u32,
+    pub first_sample: u32,
+    pub sample_count: u32,
+    _pad: u32,
+}
+
+unsafe impl bytemuck::Zeroable for SoundConstant {}
+unsafe impl bytemuck::Pod for SoundConstant {}
+
+/// Runs the sound shader over `duration` seconds of the timeline and reads the samples back.
+pub fn render_sound(
+    device: &Arc<Device>,
+    queue: &vk::Queue,
+    pipeline: &ComputePipeline,
+    descriptor_set: vk::DescriptorSet,
+    duration: f32,
+) -> Result<AudioClip> {
+    let sample_count = (duration * SOUND_SAMPLE_RATE as f32) as u32;
+    let buffer = device.create_host_buffer(
+        sample_count as u64 * 2 * size_of::<f32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        UsageFlags::DOWNLOAD,
+    )?;
+    let group_size = pipeline.workgroup_size[0].max(1);
+    let samples_per_dispatch = u16::MAX as u32 * group_size;
+
+    device.one_time_submit(queue, |device, cbuff| unsafe {
+        device.cmd_bind_pipeline(cbuff, vk::PipelineBindPoint::COMPUTE, pipeline.pipeline);
+        device.cmd_bind_descriptor_sets(
+            cbuff,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline.layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+        for first_sample in (0..sample_count).step_by(samples_per_dispatch as usize) {
+            let constant = SoundConstant {
+                samples: buffer.address,
+                sample_rate: SOUND_SAMPLE_RATE,
+                first_sample,
+                sample_count,
+                _pad: 0,
+            };
+            device.cmd_push_constants(
+                cbuff,
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&constant),
+            );
+            let count = (sample_count - first_sample).min(samples_per_dispatch);
+            device.cmd_dispatch(cbuff, count.div_ceil(group_size), 1, 1);
+        }
+        let barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ);
+        device.cmd_pipeline_barrier2(
+            cbuff,
+            &vk::DependencyInfo::default().memory_barriers(std::slice::from_ref(&barrier)),
+        );
+    })?;
+
+    // NaNs and clipping from the shader would reach the speakers as is
+    let samples: Vec<f32> = bytemuck::cast_slice::<u8, f32>(&buffer[..])
+        .iter()
+        .map(|&s| if s.is_finite() { s.clamp(-1., 1.) } else { 0. })
+        .collect();
+    Ok(AudioClip {
+        samples: samples.into(),
+        channels: 2,
+        sample_rate: SOUND_SAMPLE_RATE,
+    })
+}
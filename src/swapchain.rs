@@ -6,18 +6,21 @@ use ash::{
     vk::{self, CompositeAlphaFlagsKHR},
 };
 
-use crate::{device::Device, surface::Surface, ImageDimensions};
+use crate::{device::Device, surface::Surface, ImageDimensions, DEPTH_SUBRESOURCE_MASK};
 
 pub struct Frame {
     command_buffer: vk::CommandBuffer,
     image_available_semaphore: vk::Semaphore,
     render_finished_semaphore: vk::Semaphore,
     pub present_finished: vk::Fence,
+    /// Resources used by the commands of this frame, dropped once it has finished.
+    deferred: Vec<Box<dyn std::any::Any>>,
     device: Arc<Device>,
 }
 
 impl Frame {
     fn destroy(&mut self, pool: &vk::CommandPool) {
+        self.deferred.clear();
         unsafe {
             self.device.destroy_fence(self.present_finished, None);
             self.device
@@ -52,6 +55,7 @@ impl Frame {
             image_available_semaphore,
             render_finished_semaphore,
             present_finished,
+            deferred: vec![],
             device: device.clone(),
         })
     }
@@ -61,9 +65,26 @@ pub struct FrameGuard {
     frame: Frame,
     extent: vk::Extent2D,
     image_idx: usize,
+    depth_image: Option<vk::Image>,
     device: Arc<Device>,
 }
 
+/// When `msaa` is set rendering goes into the multisampled image and gets resolved into `view`.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorAttachment<'a> {
+    pub view: &'a vk::ImageView,
+    pub msaa: Option<(&'a vk::Image, &'a vk::ImageView)>,
+    pub clear_value: [f32; 4],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DepthAttachment<'a> {
+    pub image: &'a vk::Image,
+    pub view: &'a vk::ImageView,
+    pub msaa: Option<(&'a vk::Image, &'a vk::ImageView)>,
+    pub clear_value: f32,
+}
+
 pub struct Swapchain {
     pub images: Vec<vk::Image>,
     pub views: Vec<vk::ImageView>,
@@ -302,6 +323,7 @@ impl Swapchain {
             frame,
             extent: self.extent,
             image_idx: self.current_image,
+            depth_image: None,
             device: self.device.clone(),
         })
     }
@@ -376,21 +398,100 @@ impl FrameGuard {
         &self.frame.command_buffer
     }
 
-    pub fn begin_rendering(&mut self, view: &vk::ImageView, color: [f32; 4]) {
+    /// Keeps `resource` alive until the GPU is done with this frame.
+    pub fn defer_drop(&mut self, resource: impl std::any::Any) {
+        self.frame.deferred.push(Box::new(resource));
+    }
+
+    pub fn begin_rendering(&mut self, color: ColorAttachment, depth: Option<DepthAttachment>) {
+        let mut image_barriers = vec![];
+
         let clear_color = vk::ClearValue {
-            color: vk::ClearColorValue { float32: color },
+            color: vk::ClearColorValue {
+                float32: color.clear_value,
+            },
         };
-        let color_attachments = [vk::RenderingAttachmentInfo::default()
-            .image_view(*view)
+        let mut color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(*color.view)
             .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .resolve_image_layout(vk::ImageLayout::PRESENT_SRC_KHR)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
-            .clear_value(clear_color)];
-        let rendering_info = vk::RenderingInfo::default()
+            .clear_value(clear_color);
+        if let Some((msaa_image, msaa_view)) = color.msaa {
+            image_barriers.push(
+                vk::ImageMemoryBarrier2::default()
+                    .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                    .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                    .subresource_range(Swapchain::SUBRANGE)
+                    .image(*msaa_image)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            );
+            color_attachment = color_attachment
+                .image_view(*msaa_view)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(*color.view)
+                .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        }
+        let color_attachments = [color_attachment];
+        let mut rendering_info = vk::RenderingInfo::default()
             .render_area(self.extent.into())
             .layer_count(1)
             .color_attachments(&color_attachments);
+
+        let depth_attachment;
+        if let Some(depth) = depth {
+            let depth_barrier = vk::ImageMemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE
+                        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                )
+                .subresource_range(DEPTH_SUBRESOURCE_MASK)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL);
+            image_barriers.push(depth_barrier.image(*depth.image));
+
+            let mut attachment = vk::RenderingAttachmentInfo::default()
+                .image_view(*depth.view)
+                .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: depth.clear_value,
+                        stencil: 0,
+                    },
+                });
+            // Depth can't be averaged, sample zero is the only mode every device supports
+            if let Some((msaa_image, msaa_view)) = depth.msaa {
+                image_barriers.push(depth_barrier.image(*msaa_image));
+                attachment = attachment
+                    .image_view(*msaa_view)
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .resolve_mode(vk::ResolveModeFlags::SAMPLE_ZERO)
+                    .resolve_image_view(*depth.view)
+                    .resolve_image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL);
+            }
+            depth_attachment = attachment;
+            rendering_info = rendering_info.depth_attachment(&depth_attachment);
+            self.depth_image = Some(*depth.image);
+        }
+
+        let dependency_info = vk::DependencyInfo::default().image_memory_barriers(&image_barriers);
+        unsafe {
+            self.device
+                .cmd_pipeline_barrier2(self.frame.command_buffer, &dependency_info)
+        };
+
         unsafe {
             self.device
                 .dynamic_rendering
@@ -411,6 +512,37 @@ impl FrameGuard {
         }]);
     }
 
+    /// Starts rendering on top of what `view` holds, with the origin in the top-left
+    /// corner like window coordinates.
+    pub fn begin_overlay_rendering(&mut self, view: &vk::ImageView) {
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(*view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(self.extent.into())
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment));
+        unsafe {
+            self.device
+                .dynamic_rendering
+                .cmd_begin_rendering(self.frame.command_buffer, &rendering_info)
+        };
+        self.set_viewports(&[vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }]);
+        self.set_scissors(&[vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }]);
+    }
+
     pub fn draw(
         &mut self,
         vertex_count: u32,
@@ -429,6 +561,18 @@ impl FrameGuard {
         };
     }
 
+    pub fn draw_indirect(&mut self, buffer: vk::Buffer, offset: u64, draw_count: u32) {
+        unsafe {
+            self.device.cmd_draw_indirect(
+                self.frame.command_buffer,
+                buffer,
+                offset,
+                draw_count,
+                size_of::<vk::DrawIndirectCommand>() as _,
+            )
+        };
+    }
+
     pub fn draw_indexed(
         &mut self,
         index_count: u32,
@@ -531,11 +675,90 @@ impl FrameGuard {
         unsafe { self.device.cmd_dispatch(self.frame.command_buffer, x, y, z) };
     }
 
+    pub fn dispatch_indirect(&self, buffer: vk::Buffer, offset: u64) {
+        unsafe {
+            self.device
+                .cmd_dispatch_indirect(self.frame.command_buffer, buffer, offset)
+        };
+    }
+
+    /// Writes `data` at the start of `buffer`, after the shaders of previous frames read it
+    /// and before the shaders of this one do.
+    pub fn update_buffer(&self, buffer: vk::Buffer, data: &[u8]) {
+        let shader_stages =
+            vk::PipelineStageFlags2::ALL_GRAPHICS | vk::PipelineStageFlags2::COMPUTE_SHADER;
+        let before = vk::MemoryBarrier2::default()
+            .src_stage_mask(shader_stages)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE);
+        let after = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(shader_stages)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                self.frame.command_buffer,
+                &vk::DependencyInfo::default().memory_barriers(std::slice::from_ref(&before)),
+            );
+            self.device
+                .cmd_update_buffer(self.frame.command_buffer, buffer, 0, data);
+            self.device.cmd_pipeline_barrier2(
+                self.frame.command_buffer,
+                &vk::DependencyInfo::default().memory_barriers(std::slice::from_ref(&after)),
+            );
+        }
+    }
+
+    /// Makes shader writes from `src_stage` visible to the following indirect commands.
+    pub fn indirect_barrier(&self, src_stage: vk::PipelineStageFlags2) {
+        let memory_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(src_stage)
+            .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::DRAW_INDIRECT)
+            .dst_access_mask(vk::AccessFlags2::INDIRECT_COMMAND_READ);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                self.frame.command_buffer,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+            )
+        };
+    }
+
     pub fn end_rendering(&mut self) {
         unsafe {
             self.device
                 .dynamic_rendering
                 .cmd_end_rendering(self.frame.command_buffer)
         };
+
+        // Leave depth readable for the passes that sample it afterwards
+        if let Some(depth_image) = self.depth_image.take() {
+            let image_barrier = vk::ImageMemoryBarrier2::default()
+                .src_stage_mask(
+                    vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                )
+                .dst_stage_mask(
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                )
+                .src_access_mask(
+                    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE
+                        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                )
+                .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+                .subresource_range(DEPTH_SUBRESOURCE_MASK)
+                .image(depth_image)
+                .old_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            let dependency_info = vk::DependencyInfo::default()
+                .image_memory_barriers(slice::from_ref(&image_barrier));
+            unsafe {
+                self.device
+                    .cmd_pipeline_barrier2(self.frame.command_buffer, &dependency_info)
+            };
+        }
     }
 }
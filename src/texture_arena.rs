@@ -1,16 +1,27 @@
 use std::{mem::ManuallyDrop, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use ash::{
     prelude::VkResult,
     vk::{self, DeviceMemory},
 };
 use gpu_alloc::{MemoryBlock, UsageFlags};
+use parking_lot::Mutex;
 
-use crate::{Device, ImageDimensions, COLOR_SUBRESOURCE_MASK};
+use crate::{
+    texture_loader::{mip_count, TextureData},
+    transfer::{TransferQueue, UploadId},
+    Device, FrameGuard, ImageDimensions, COLOR_SUBRESOURCE_MASK, DEPTH_SUBRESOURCE_MASK,
+};
 
 pub const LINEAR_SAMPLER_IDX: usize = 0;
 pub const NEAREST_SAMPLER_IDX: usize = 1;
+pub const LINEAR_CLAMP_SAMPLER_IDX: usize = 2;
+pub const NEAREST_CLAMP_SAMPLER_IDX: usize = 3;
+pub const LINEAR_REPEAT_SAMPLER_IDX: usize = 4;
+pub const NEAREST_REPEAT_SAMPLER_IDX: usize = 5;
+pub const ANISOTROPIC_SAMPLER_IDX: usize = 6;
+pub const SHADOW_SAMPLER_IDX: usize = 7;
 
 pub const PREV_FRAME_IMAGE_IDX: usize = 0;
 pub const GENERIC_IMAGE1_IDX: usize = 1;
@@ -18,9 +29,17 @@ pub const GENERIC_IMAGE2_IDX: usize = 2;
 pub const DITHER_IMAGE_IDX: usize = 3;
 pub const NOISE_IMAGE_IDX: usize = 4;
 pub const BLUE_IMAGE_IDX: usize = 5;
+pub const DEPTH_IMAGE_IDX: usize = 6;
+
+/// Slots below are owned by the arena and can't be removed.
+const RESERVED_SLOTS: u32 = DEPTH_IMAGE_IDX as u32 + 1;
 
-pub const SCREENSIZED_IMAGE_INDICES: [usize; 3] =
-    [PREV_FRAME_IMAGE_IDX, GENERIC_IMAGE1_IDX, GENERIC_IMAGE2_IDX];
+pub const SCREENSIZED_IMAGE_INDICES: [usize; 4] = [
+    PREV_FRAME_IMAGE_IDX,
+    GENERIC_IMAGE1_IDX,
+    GENERIC_IMAGE2_IDX,
+    DEPTH_IMAGE_IDX,
+];
 
 pub struct Image {
     pub image: vk::Image,
@@ -50,7 +69,7 @@ impl Image {
         })
     }
 
-    fn desctroy(&mut self, device: &Device) {
+    pub(crate) fn desctroy(&mut self, device: &Device) {
         unsafe {
             let memory = ManuallyDrop::take(&mut self.memory);
             device.dealloc_memory(memory);
@@ -59,13 +78,77 @@ impl Image {
     }
 }
 
+struct Texture {
+    image: Image,
+    view: vk::ImageView,
+    view_type: vk::ImageViewType,
+    extent: vk::Extent3D,
+}
+
+impl Texture {
+    fn destroy(&mut self, device: &Device) {
+        self.image.desctroy(device);
+        unsafe { device.destroy_image_view(self.view, None) };
+    }
+}
+
+/// Texture taken out of its slot. It's destroyed, and the slot freed, once the frames
+/// that could still sample it have finished.
+struct RetiredTexture {
+    texture: Texture,
+    slot: Option<u32>,
+    free_slots: Arc<Mutex<Vec<u32>>>,
+    device: Arc<Device>,
+}
+
+impl Drop for RetiredTexture {
+    fn drop(&mut self) {
+        self.texture.destroy(&self.device);
+        if let Some(slot) = self.slot {
+            self.free_slots.lock().push(slot);
+        }
+    }
+}
+
+/// Texture whose data is still in flight on the transfer queue.
+struct PendingTexture {
+    upload: UploadId,
+    slot: u32,
+    texture: Texture,
+    mip_levels: u32,
+    layers: u32,
+    /// Only the first level got uploaded, the rest of the chain is blitted from it.
+    generate_mips: bool,
+    /// A newer upload or a removal of the slot happened meanwhile.
+    stale: bool,
+    /// The slot got removed before anything landed in it.
+    free_slot: bool,
+}
+
+fn image_kind(view_type: vk::ImageViewType) -> (vk::ImageType, vk::ImageCreateFlags) {
+    match view_type {
+        vk::ImageViewType::CUBE => (
+            vk::ImageType::TYPE_2D,
+            vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        ),
+        vk::ImageViewType::TYPE_3D => (vk::ImageType::TYPE_3D, vk::ImageCreateFlags::empty()),
+        _ => (vk::ImageType::TYPE_2D, vk::ImageCreateFlags::empty()),
+    }
+}
+
 const IMAGES_COUNT: u32 = 2048;
 const SAMPLER_COUNT: u32 = 8;
 
 pub struct TextureArena {
-    pub images: Vec<Image>,
+    textures: Vec<Option<Texture>>,
+    /// Create infos of the reserved slots, the screen-sized ones get recreated from them.
     pub image_infos: Vec<vk::ImageCreateInfo<'static>>,
-    pub views: Vec<vk::ImageView>,
+    free_slots: Arc<Mutex<Vec<u32>>>,
+    pending: Vec<PendingTexture>,
+    retired: Vec<RetiredTexture>,
+    /// The depth image got created since the last frame and has no layout yet.
+    depth_undefined: bool,
+    placeholders: [Texture; 3],
     pub samplers: [vk::Sampler; SAMPLER_COUNT as usize],
     descriptor_pool: vk::DescriptorPool,
     pub images_set: vk::DescriptorSet,
@@ -75,14 +158,47 @@ pub struct TextureArena {
 
 impl TextureArena {
     pub fn image_count(&self) -> usize {
-        self.images.len()
+        self.textures.iter().flatten().count()
     }
 
-    pub fn new(device: &Arc<Device>, queue: &vk::Queue, extent: vk::Extent2D) -> Result<Self> {
+    /// Panics if the slot is empty.
+    pub fn image(&self, idx: usize) -> &vk::Image {
+        &self.texture(idx).image.image
+    }
+
+    /// Panics if the slot is empty.
+    pub fn view(&self, idx: usize) -> &vk::ImageView {
+        &self.texture(idx).view
+    }
+
+    fn texture(&self, idx: usize) -> &Texture {
+        self.textures[idx]
+            .as_ref()
+            .unwrap_or_else(|| panic!("Texture slot {idx} is empty"))
+    }
+
+    pub fn new(
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        transfer: &mut TransferQueue,
+        extent: vk::Extent2D,
+        depth_format: vk::Format,
+    ) -> Result<Self> {
+        let max_images = device
+            .descriptor_indexing_props
+            .max_descriptor_set_update_after_bind_sampled_images;
+        if max_images < IMAGES_COUNT * 3 {
+            bail!(
+                "The device allows {max_images} sampled images in a descriptor set, \
+                 {} are needed for 2D, cube and 3D textures",
+                IMAGES_COUNT * 3
+            );
+        }
+
         let pool_sizes = [
             vk::DescriptorPoolSize::default()
                 .ty(vk::DescriptorType::SAMPLED_IMAGE)
-                .descriptor_count(IMAGES_COUNT),
+                .descriptor_count(IMAGES_COUNT * 3),
             vk::DescriptorPoolSize::default()
                 .ty(vk::DescriptorType::SAMPLER)
                 .descriptor_count(SAMPLER_COUNT),
@@ -104,6 +220,8 @@ impl TextureArena {
             | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
             | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
         let binding_flags = [
+            binding_flags,
+            binding_flags,
             binding_flags,
             binding_flags | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
         ];
@@ -118,16 +236,20 @@ impl TextureArena {
                     .descriptor_indexing_props
                     .max_descriptor_set_update_after_bind_samplers,
             );
+        // 2D, cube and 3D images share indices, each one is written into the array of its type
         let image_set_layout_binding = vk::DescriptorSetLayoutBinding::default()
             .binding(1)
             .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
             .stage_flags(vk::ShaderStageFlags::ALL_GRAPHICS | vk::ShaderStageFlags::COMPUTE)
-            .descriptor_count(
-                device
-                    .descriptor_indexing_props
-                    .max_descriptor_set_update_after_bind_sampled_images,
-            );
-        let bindings = [sampler_set_layout_binding, image_set_layout_binding];
+            .descriptor_count(IMAGES_COUNT);
+        let cube_set_layout_binding = image_set_layout_binding.binding(2);
+        let volume_set_layout_binding = image_set_layout_binding.binding(3);
+        let bindings = [
+            sampler_set_layout_binding,
+            image_set_layout_binding,
+            cube_set_layout_binding,
+            volume_set_layout_binding,
+        ];
         let images_set_layout = unsafe {
             device.create_descriptor_set_layout(
                 &vk::DescriptorSetLayoutCreateInfo::default()
@@ -146,94 +268,82 @@ impl TextureArena {
             .push_next(&mut variable_info);
         let images_set = unsafe { device.allocate_descriptor_sets(&allocate_info)? }[0];
 
-        let image_infos: [_; 3] = std::array::from_fn(|_| {
-            vk::ImageCreateInfo::default()
-                .extent(vk::Extent3D {
-                    width: extent.width,
-                    height: extent.height,
-                    depth: 1,
-                })
-                .image_type(vk::ImageType::TYPE_2D)
-                .format(vk::Format::R8G8B8A8_SRGB)
-                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .mip_levels(1)
-                .array_layers(1)
-                .tiling(vk::ImageTiling::OPTIMAL)
-        });
-
-        let images = image_infos
-            .iter()
-            .map(|info| Image::new(device, info, gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS))
-            .collect::<Result<Vec<_>>>()?;
+        let screen_info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL);
+        let mut image_infos = vec![screen_info; RESERVED_SLOTS as usize];
+        image_infos[DEPTH_IMAGE_IDX] = screen_info
+            .format(depth_format)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT);
 
-        let views = images
-            .iter()
-            .zip(image_infos)
-            .map(|(image, info)| device.create_2d_view(&image.image, info.format))
-            .collect::<VkResult<Vec<_>>>()?;
+        let mut textures: Vec<_> = (0..RESERVED_SLOTS).map(|_| None).collect();
+        for i in SCREENSIZED_IMAGE_INDICES {
+            let info = &image_infos[i];
+            let image = Image::new(device, info, gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS)?;
+            let view = device.create_2d_view(&image.image, info.format)?;
+            textures[i] = Some(Texture {
+                image,
+                view,
+                view_type: vk::ImageViewType::TYPE_2D,
+                extent: info.extent,
+            });
+        }
 
-        for (i, view) in views.iter().enumerate() {
-            let image_info = vk::DescriptorImageInfo::default()
-                .image_view(*view)
-                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
-            let write = vk::WriteDescriptorSet::default()
+        let samplers = Self::create_samplers(device)?;
+        for (i, sampler) in samplers.iter().enumerate() {
+            let descriptor_image_info = vk::DescriptorImageInfo::default().sampler(*sampler);
+            let desc_write = vk::WriteDescriptorSet::default()
+                .descriptor_type(vk::DescriptorType::SAMPLER)
                 .dst_set(images_set)
-                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
-                .dst_binding(1)
-                .image_info(std::slice::from_ref(&image_info))
+                .dst_binding(0)
+                .image_info(std::slice::from_ref(&descriptor_image_info))
                 .dst_array_element(i as _);
-            unsafe { device.update_descriptor_sets(&[write], &[]) };
+            unsafe { device.update_descriptor_sets(&[desc_write], &[]) };
         }
 
-        let mut samplers = [vk::Sampler::null(); SAMPLER_COUNT as usize];
-        let mut sampler_create_info = vk::SamplerCreateInfo::default()
-            .min_filter(vk::Filter::LINEAR)
-            .mag_filter(vk::Filter::LINEAR)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .address_mode_u(vk::SamplerAddressMode::MIRRORED_REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::MIRRORED_REPEAT)
-            .address_mode_w(vk::SamplerAddressMode::MIRRORED_REPEAT)
-            .max_lod(vk::LOD_CLAMP_NONE);
-        let sampler = unsafe { device.create_sampler(&sampler_create_info, None)? };
-        let descriptor_image_info = vk::DescriptorImageInfo::default().sampler(sampler);
-        let mut desc_write = vk::WriteDescriptorSet::default()
-            .descriptor_type(vk::DescriptorType::SAMPLER)
-            .dst_set(images_set)
-            .dst_binding(0)
-            .image_info(std::slice::from_ref(&descriptor_image_info))
-            .dst_array_element(0);
-        unsafe { device.update_descriptor_sets(&[desc_write], &[]) };
-        samplers[0] = sampler;
-
-        sampler_create_info = sampler_create_info
-            .mag_filter(vk::Filter::NEAREST)
-            .min_filter(vk::Filter::NEAREST);
-        let sampler = unsafe { device.create_sampler(&sampler_create_info, None)? };
-        let descriptor_image_info = vk::DescriptorImageInfo::default().sampler(sampler);
-        desc_write = desc_write.image_info(std::slice::from_ref(&descriptor_image_info));
-        unsafe { device.update_descriptor_sets(&[desc_write], &[]) };
-        samplers[1] = sampler;
-
         let mut texture_arena = Self {
-            images,
-            image_infos: image_infos.to_vec(),
-            views,
+            textures,
+            image_infos,
+            free_slots: Arc::default(),
+            pending: vec![],
+            retired: vec![],
+            depth_undefined: true,
+            placeholders: Self::create_placeholders(device, queue)?,
             samplers,
             descriptor_pool,
             images_set,
             images_set_layout,
             device: device.clone(),
         };
+        for i in SCREENSIZED_IMAGE_INDICES {
+            let view = *texture_arena.view(i);
+            texture_arena.write_descriptor(i as u32, view, vk::ImageViewType::TYPE_2D);
+        }
 
         texture_arena.device.name_object(
-            texture_arena.images[PREV_FRAME_IMAGE_IDX].image,
+            *texture_arena.image(PREV_FRAME_IMAGE_IDX),
             "Previous Frame Image",
         );
         texture_arena.device.name_object(
-            texture_arena.views[PREV_FRAME_IMAGE_IDX],
+            *texture_arena.view(PREV_FRAME_IMAGE_IDX),
             "Previous Frame Image View",
         );
+        texture_arena
+            .device
+            .name_object(*texture_arena.image(DEPTH_IMAGE_IDX), "Depth Image");
+        texture_arena
+            .device
+            .name_object(*texture_arena.view(DEPTH_IMAGE_IDX), "Depth Image View");
 
         let bytes = include_bytes!("../assets/dither.dds");
         let dds = ddsfile::Dds::read(&bytes[..])?;
@@ -251,115 +361,637 @@ impl TextureArena {
             .mip_levels(1)
             .array_layers(1)
             .tiling(vk::ImageTiling::OPTIMAL);
-        texture_arena.push_image(device, queue, info, dds.get_data(0)?)?;
-        texture_arena
-            .device
-            .name_object(texture_arena.images[DITHER_IMAGE_IDX].image, "Dither Image");
-        texture_arena
-            .device
-            .name_object(texture_arena.views[DITHER_IMAGE_IDX], "Dither Image View");
+        texture_arena.push_image(transfer, DITHER_IMAGE_IDX, info, dds.get_data(0)?, "Dither")?;
 
         let bytes = include_bytes!("../assets/noise.dds");
         let dds = ddsfile::Dds::read(&bytes[..])?;
         extent.width = dds.get_width();
         extent.height = dds.get_height();
         info.extent = extent;
-        texture_arena.push_image(device, queue, info, dds.get_data(0)?)?;
-        texture_arena
-            .device
-            .name_object(texture_arena.images[NOISE_IMAGE_IDX].image, "Noise Image");
-        texture_arena
-            .device
-            .name_object(texture_arena.views[NOISE_IMAGE_IDX], "Noise Image View");
+        texture_arena.push_image(transfer, NOISE_IMAGE_IDX, info, dds.get_data(0)?, "Noise")?;
 
         let bytes = include_bytes!("../assets/BLUE_RGBA_0.dds");
         let dds = ddsfile::Dds::read(&bytes[..])?;
         extent.width = dds.get_width();
         extent.height = dds.get_height();
         info.extent = extent;
-        texture_arena.push_image(device, queue, info, dds.get_data(0)?)?;
-        texture_arena.device.name_object(
-            texture_arena.images[BLUE_IMAGE_IDX].image,
-            "Blue Noise Image",
-        );
-        texture_arena
-            .device
-            .name_object(texture_arena.views[BLUE_IMAGE_IDX], "Blue Noise Image View");
+        texture_arena.push_image(
+            transfer,
+            BLUE_IMAGE_IDX,
+            info,
+            dds.get_data(0)?,
+            "Blue Noise",
+        )?;
+
+        // Built-in textures must be ready by the first frame
+        transfer.wait_idle()?;
 
         Ok(texture_arena)
     }
 
-    pub fn push_image(
+    fn create_samplers(device: &Device) -> VkResult<[vk::Sampler; SAMPLER_COUNT as usize]> {
+        let address_mode = |info: vk::SamplerCreateInfo<'static>, mode| {
+            info.address_mode_u(mode)
+                .address_mode_v(mode)
+                .address_mode_w(mode)
+        };
+        let linear = address_mode(
+            vk::SamplerCreateInfo::default()
+                .min_filter(vk::Filter::LINEAR)
+                .mag_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .max_lod(vk::LOD_CLAMP_NONE),
+            vk::SamplerAddressMode::MIRRORED_REPEAT,
+        );
+        let nearest = linear
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST);
+        let trilinear = linear.mipmap_mode(vk::SamplerMipmapMode::LINEAR);
+        let clamp = vk::SamplerAddressMode::CLAMP_TO_EDGE;
+        let repeat = vk::SamplerAddressMode::REPEAT;
+
+        let mut infos = [linear; SAMPLER_COUNT as usize];
+        infos[LINEAR_SAMPLER_IDX] = linear;
+        infos[NEAREST_SAMPLER_IDX] = nearest;
+        infos[LINEAR_CLAMP_SAMPLER_IDX] = address_mode(trilinear, clamp);
+        infos[NEAREST_CLAMP_SAMPLER_IDX] = address_mode(nearest, clamp);
+        infos[LINEAR_REPEAT_SAMPLER_IDX] = address_mode(trilinear, repeat);
+        infos[NEAREST_REPEAT_SAMPLER_IDX] = address_mode(nearest, repeat);
+        // Falls back to trilinear when the device lacks anisotropic filtering
+        infos[ANISOTROPIC_SAMPLER_IDX] = address_mode(trilinear, repeat)
+            .anisotropy_enable(device.features.sampler_anisotropy == vk::TRUE)
+            .max_anisotropy(
+                device
+                    .device_properties
+                    .limits
+                    .max_sampler_anisotropy
+                    .min(16.),
+            );
+        infos[SHADOW_SAMPLER_IDX] = address_mode(linear, clamp)
+            .compare_enable(true)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL);
+
+        let mut samplers = [vk::Sampler::null(); SAMPLER_COUNT as usize];
+        for (sampler, info) in samplers.iter_mut().zip(&infos) {
+            *sampler = unsafe { device.create_sampler(info, None)? };
+        }
+        Ok(samplers)
+    }
+
+    /// Zeroed textures bound to the slots whose data is not there yet, one per view type.
+    fn create_placeholders(device: &Arc<Device>, queue: &vk::Queue) -> Result<[Texture; 3]> {
+        let extent = vk::Extent3D {
+            width: 1,
+            height: 1,
+            depth: 1,
+        };
+        let create = |view_type| -> Result<Texture> {
+            let (image_type, flags) = image_kind(view_type);
+            let layers = if view_type == vk::ImageViewType::CUBE {
+                6
+            } else {
+                1
+            };
+            let info = vk::ImageCreateInfo::default()
+                .flags(flags)
+                .extent(extent)
+                .image_type(image_type)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .mip_levels(1)
+                .array_layers(layers)
+                .tiling(vk::ImageTiling::OPTIMAL);
+            let image = Image::new(device, &info, UsageFlags::FAST_DEVICE_ACCESS)?;
+            let view = device.create_view(&image.image, info.format, view_type)?;
+            Ok(Texture {
+                image,
+                view,
+                view_type,
+                extent,
+            })
+        };
+        let placeholders = [
+            create(vk::ImageViewType::TYPE_2D)?,
+            create(vk::ImageViewType::CUBE)?,
+            create(vk::ImageViewType::TYPE_3D)?,
+        ];
+
+        device.one_time_submit(queue, |device, cbuff| unsafe {
+            for texture in &placeholders {
+                let image = texture.image.image;
+                let barrier = vk::ImageMemoryBarrier2::default()
+                    .subresource_range(COLOR_SUBRESOURCE_MASK)
+                    .dst_stage_mask(vk::PipelineStageFlags2::CLEAR)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .image(image);
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default()
+                        .image_memory_barriers(std::slice::from_ref(&barrier)),
+                );
+                device.cmd_clear_color_image(
+                    cbuff,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &vk::ClearColorValue::default(),
+                    &[COLOR_SUBRESOURCE_MASK],
+                );
+                let layers = match texture.view_type {
+                    vk::ImageViewType::CUBE => 6,
+                    _ => 1,
+                };
+                device.generate_mipmaps(&cbuff, &image, extent, 1, layers);
+            }
+        })?;
+
+        Ok(placeholders)
+    }
+
+    fn placeholder(&self, view_type: vk::ImageViewType) -> vk::ImageView {
+        let idx = match view_type {
+            vk::ImageViewType::CUBE => 1,
+            vk::ImageViewType::TYPE_3D => 2,
+            _ => 0,
+        };
+        self.placeholders[idx].view
+    }
+
+    fn push_image(
         &mut self,
-        device: &Arc<Device>,
-        queue: &vk::Queue,
-        info: vk::ImageCreateInfo,
+        transfer: &mut TransferQueue,
+        idx: usize,
+        info: vk::ImageCreateInfo<'static>,
         data: &[u8],
+        name: &str,
+    ) -> Result<()> {
+        self.image_infos[idx] = info;
+        let region = vk::BufferImageCopy::default()
+            .image_extent(info.extent)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_array_layer: 0,
+                layer_count: 1,
+                mip_level: 0,
+            });
+        self.queue_upload(
+            transfer,
+            idx as u32,
+            &info,
+            vk::ImageViewType::TYPE_2D,
+            &[region],
+            data,
+        )?;
+        let texture = &self.pending.last().unwrap().texture;
+        self.device
+            .name_object(texture.image.image, &format!("{name} Image"));
+        self.device
+            .name_object(texture.view, &format!("{name} Image View"));
+        Ok(())
+    }
+
+    /// Takes a free slot, reusing the ones released by [`Self::remove_texture`] first.
+    fn allocate_slot(&mut self) -> Result<u32> {
+        if let Some(idx) = self.free_slots.lock().pop() {
+            return Ok(idx);
+        }
+        let idx = self.textures.len() as u32;
+        if idx >= IMAGES_COUNT {
+            bail!("All {IMAGES_COUNT} texture slots are in use");
+        }
+        self.textures.push(None);
+        Ok(idx)
+    }
+
+    /// Starts uploading the texture into a new slot. Shaders see a zeroed texture
+    /// until it lands with [`Self::land_uploads`].
+    pub fn push_texture(
+        &mut self,
+        transfer: &mut TransferQueue,
+        texture: &TextureData,
     ) -> Result<u32> {
-        let image = { Image::new(device, &info, UsageFlags::FAST_DEVICE_ACCESS)? };
-        let mut staging = device.create_host_buffer(
-            image.memory.size(),
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            UsageFlags::UPLOAD,
+        let idx = self.allocate_slot()?;
+        if let Err(err) = self.upload_texture(transfer, idx, texture) {
+            self.free_slots.lock().push(idx);
+            return Err(err);
+        }
+        self.write_descriptor(idx, self.placeholder(texture.view_type), texture.view_type);
+        Ok(idx)
+    }
+
+    /// Uploads the texture into an existing slot, shaders keep using the same index and
+    /// see the previous image until the new one lands. The previous image lives until
+    /// the frames in flight are done with it.
+    pub fn replace_texture(
+        &mut self,
+        transfer: &mut TransferQueue,
+        idx: u32,
+        texture: &TextureData,
+    ) -> Result<()> {
+        let occupied = self.textures.get(idx as usize).is_some_and(Option::is_some)
+            || self.pending.iter().any(|pending| pending.slot == idx);
+        if !occupied {
+            bail!("Texture slot {idx} is empty");
+        }
+        self.upload_texture(transfer, idx, texture)
+    }
+
+    /// Frees the slot of a pushed texture. Its index stays unused until the frames
+    /// in flight are done with the image, see [`Self::release_retired`].
+    pub fn remove_texture(&mut self, idx: u32) -> Result<()> {
+        if idx < RESERVED_SLOTS {
+            bail!("Texture slot {idx} is reserved");
+        }
+        let newest_pending = self.pending.iter().rposition(|pending| pending.slot == idx);
+        for pending in self
+            .pending
+            .iter_mut()
+            .filter(|pending| pending.slot == idx)
+        {
+            pending.stale = true;
+        }
+        match self.textures.get_mut(idx as usize).and_then(Option::take) {
+            Some(texture) => {
+                self.write_descriptor(idx, self.placeholder(texture.view_type), texture.view_type);
+                self.retire(texture, Some(idx));
+            }
+            // The slot gets freed once its upload lands
+            None => match newest_pending {
+                Some(pending) => self.pending[pending].free_slot = true,
+                None => bail!("Texture slot {idx} is empty"),
+            },
+        }
+        Ok(())
+    }
+
+    fn retire(&mut self, texture: Texture, slot: Option<u32>) {
+        self.retired.push(RetiredTexture {
+            texture,
+            slot,
+            free_slots: self.free_slots.clone(),
+            device: self.device.clone(),
+        });
+    }
+
+    /// Hands the removed and replaced textures over to `frame`, they get destroyed
+    /// after it and every frame submitted before it have finished.
+    pub fn release_retired(&mut self, frame: &mut FrameGuard) {
+        for texture in self.retired.drain(..) {
+            frame.defer_drop(texture);
+        }
+    }
+
+    /// Takes ownership of the uploads finished on the transfer queue, generates their
+    /// mips in `frame` and binds them to their slots. Call before [`Self::release_retired`].
+    pub fn land_uploads(&mut self, frame: &FrameGuard, transfer: &mut TransferQueue) {
+        let command_buffer = *frame.command_buffer();
+        let finished = transfer.acquire_finished(&command_buffer);
+        if finished.is_empty() {
+            return;
+        }
+
+        let (landed, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|pending| finished.contains(&pending.upload));
+        self.pending = pending;
+        for pending in landed {
+            let PendingTexture {
+                slot,
+                texture,
+                mip_levels,
+                layers,
+                generate_mips,
+                stale,
+                free_slot,
+                ..
+            } = pending;
+            if stale {
+                self.retire(texture, free_slot.then_some(slot));
+                continue;
+            }
+
+            self.record_mips(
+                &command_buffer,
+                texture.image.image,
+                texture.extent,
+                mip_levels,
+                layers,
+                generate_mips,
+            );
+            self.write_descriptor(slot, texture.view, texture.view_type);
+            let view_type = texture.view_type;
+            if let Some(old) = self.textures[slot as usize].replace(texture) {
+                if old.view_type != view_type {
+                    self.write_descriptor(slot, self.placeholder(old.view_type), old.view_type);
+                }
+                self.retire(old, None);
+            }
+        }
+    }
+
+    fn upload_texture(
+        &mut self,
+        transfer: &mut TransferQueue,
+        idx: u32,
+        texture: &TextureData,
+    ) -> Result<()> {
+        let features = self
+            .device
+            .format_properties(texture.format)
+            .optimal_tiling_features;
+        if !features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+            bail!(
+                "Texture format {:?} is not supported by the device",
+                texture.format
+            );
+        }
+        let mip_levels = self.mip_levels(texture);
+        let generate_mips = self.generates_mips(texture);
+        let usage = match generate_mips {
+            true => vk::ImageUsageFlags::TRANSFER_SRC,
+            false => vk::ImageUsageFlags::empty(),
+        };
+        let (image_type, flags) = image_kind(texture.view_type);
+        let info = vk::ImageCreateInfo::default()
+            .flags(flags)
+            .extent(texture.extent)
+            .image_type(image_type)
+            .format(texture.format)
+            .usage(usage | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(mip_levels)
+            .array_layers(texture.layers)
+            .tiling(vk::ImageTiling::OPTIMAL);
+        self.queue_upload(
+            transfer,
+            idx,
+            &info,
+            texture.view_type,
+            &texture.regions,
+            &texture.data,
         )?;
-        staging[..data.len()].copy_from_slice(data);
+        self.pending.last_mut().unwrap().generate_mips = generate_mips;
+        Ok(())
+    }
 
-        device.one_time_submit(queue, |device, cbuff| unsafe {
-            let mut image_barrier = vk::ImageMemoryBarrier2::default()
-                .subresource_range(COLOR_SUBRESOURCE_MASK)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .image(image.image);
-            let dependency_info = vk::DependencyInfo::default()
-                .image_memory_barriers(std::slice::from_ref(&image_barrier));
-            device.cmd_pipeline_barrier2(cbuff, &dependency_info);
-            let regions = vk::BufferImageCopy::default()
-                .image_extent(info.extent)
-                .image_subresource(vk::ImageSubresourceLayers {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                    mip_level: 0,
-                });
-            device.cmd_copy_buffer_to_image(
-                cbuff,
-                staging.buffer,
-                image.image,
+    fn queue_upload(
+        &mut self,
+        transfer: &mut TransferQueue,
+        idx: u32,
+        info: &vk::ImageCreateInfo,
+        view_type: vk::ImageViewType,
+        regions: &[vk::BufferImageCopy],
+        data: &[u8],
+    ) -> Result<()> {
+        let mut image = Image::new(&self.device, info, UsageFlags::FAST_DEVICE_ACCESS)?;
+        let view = match self
+            .device
+            .create_view(&image.image, info.format, view_type)
+        {
+            Ok(view) => view,
+            Err(err) => {
+                image.desctroy(&self.device);
+                return Err(err.into());
+            }
+        };
+        let mut texture = Texture {
+            image,
+            view,
+            view_type,
+            extent: info.extent,
+        };
+        let upload = match transfer.upload_image(texture.image.image, regions, data) {
+            Ok(upload) => upload,
+            Err(err) => {
+                texture.destroy(&self.device);
+                return Err(err);
+            }
+        };
+
+        // The newest upload of a slot wins over the ones still in flight
+        for pending in self
+            .pending
+            .iter_mut()
+            .filter(|pending| pending.slot == idx)
+        {
+            pending.stale = true;
+        }
+        self.pending.push(PendingTexture {
+            upload,
+            slot: idx,
+            texture,
+            mip_levels: info.mip_levels,
+            layers: info.array_layers,
+            generate_mips: false,
+            stale: false,
+            free_slot: false,
+        });
+        Ok(())
+    }
+
+    fn mip_levels(&self, texture: &TextureData) -> u32 {
+        match texture.levels == 1 && self.device.supports_linear_blit(texture.format) {
+            true => mip_count(texture.extent),
+            false => texture.levels,
+        }
+    }
+
+    /// Files shipping their own chain, compressed ones included, keep it as uploaded.
+    fn generates_mips(&self, texture: &TextureData) -> bool {
+        texture.levels == 1 && self.mip_levels(texture) > 1
+    }
+
+    /// Blits the chain from the first level or moves the uploaded levels as they are,
+    /// either way the whole image ends up ready for sampling.
+    fn record_mips(
+        &self,
+        command_buffer: &vk::CommandBuffer,
+        image: vk::Image,
+        extent: vk::Extent3D,
+        mip_levels: u32,
+        layers: u32,
+        generate: bool,
+    ) {
+        if generate {
+            self.device
+                .generate_mipmaps(command_buffer, &image, extent, mip_levels, layers);
+            return;
+        }
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(COLOR_SUBRESOURCE_MASK)
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image(image);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                *command_buffer,
+                &vk::DependencyInfo::default()
+                    .image_memory_barriers(std::slice::from_ref(&barrier)),
+            )
+        };
+    }
+
+    /// Records a copy of new texels into a landed texture with the same layout and
+    /// regenerates the rest of its chain when it only has the first level. Skipped while
+    /// the slot still waits for its first upload or holds a texture of a different size.
+    pub fn record_upload(
+        &self,
+        command_buffer: &vk::CommandBuffer,
+        idx: u32,
+        staging: vk::Buffer,
+        texture: &TextureData,
+    ) {
+        let Some(Some(current)) = self.textures.get(idx as usize) else {
+            return;
+        };
+        if current.extent != texture.extent {
+            return;
+        }
+        let image = current.image.image;
+        let mip_levels = self.mip_levels(texture);
+        let image_barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(COLOR_SUBRESOURCE_MASK)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::SHADER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .image(image);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                *command_buffer,
+                &vk::DependencyInfo::default()
+                    .image_memory_barriers(std::slice::from_ref(&image_barrier)),
+            );
+            self.device.cmd_copy_buffer_to_image(
+                *command_buffer,
+                staging,
+                image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[regions],
+                &texture.regions,
             );
-            image_barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-            image_barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-            let dependency_info = vk::DependencyInfo::default()
-                .image_memory_barriers(std::slice::from_ref(&image_barrier));
-            device.cmd_pipeline_barrier2(cbuff, &dependency_info);
-        })?;
-
-        let view = self.device.create_2d_view(&image.image, info.format)?;
-        let idx = self.images.len() as u32;
+        }
+        self.record_mips(
+            command_buffer,
+            image,
+            texture.extent,
+            mip_levels,
+            texture.layers,
+            self.generates_mips(texture),
+        );
+    }
 
+    fn write_descriptor(&self, idx: u32, view: vk::ImageView, view_type: vk::ImageViewType) {
+        let binding = match view_type {
+            vk::ImageViewType::CUBE => 2,
+            vk::ImageViewType::TYPE_3D => 3,
+            _ => 1,
+        };
         let image_info = vk::DescriptorImageInfo::default()
             .image_view(view)
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
         let write = vk::WriteDescriptorSet::default()
             .dst_set(self.images_set)
             .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
-            .dst_binding(1)
+            .dst_binding(binding)
             .image_info(std::slice::from_ref(&image_info))
             .dst_array_element(idx);
-        unsafe { device.update_descriptor_sets(&[write], &[]) };
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+    }
 
-        self.images.push(image);
-        self.views.push(view);
+    /// Gives a screen-sized image a full mip chain sized to its extent, applied on the
+    /// next [`Self::update_images`].
+    pub fn set_screen_mips(&mut self, idx: usize, mips: bool) {
+        let info = &mut self.image_infos[idx];
+        if mips {
+            info.mip_levels = mip_count(info.extent);
+            info.usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        } else {
+            info.mip_levels = 1;
+            info.usage &= !vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+    }
 
-        Ok(idx)
+    /// Regenerates the mips of a screen-sized image from its first level, which is
+    /// expected in `layout`. Does nothing for images without mips.
+    pub fn record_screen_mips(
+        &self,
+        command_buffer: &vk::CommandBuffer,
+        idx: usize,
+        layout: vk::ImageLayout,
+    ) {
+        let info = &self.image_infos[idx];
+        if info.mip_levels == 1 {
+            return;
+        }
+        let image = *self.image(idx);
+        let first = vk::ImageMemoryBarrier2::default()
+            .subresource_range(vk::ImageSubresourceRange {
+                level_count: 1,
+                ..COLOR_SUBRESOURCE_MASK
+            })
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .image(image);
+        let rest = first
+            .subresource_range(vk::ImageSubresourceRange {
+                base_mip_level: 1,
+                ..COLOR_SUBRESOURCE_MASK
+            })
+            .src_access_mask(vk::AccessFlags2::SHADER_READ)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                *command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[first, rest]),
+            )
+        };
+        self.device
+            .generate_mipmaps(command_buffer, &image, info.extent, info.mip_levels, 1);
     }
 
+    /// Moves a depth image created since the last frame to the layout the compute pass
+    /// samples it in, rendering only moves it there at the end of the render pass.
+    pub fn record_depth_layout(&mut self, frame: &FrameGuard) {
+        if !std::mem::take(&mut self.depth_undefined) {
+            return;
+        }
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(DEPTH_SUBRESOURCE_MASK)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image(*self.image(DEPTH_IMAGE_IDX));
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                *frame.command_buffer(),
+                &vk::DependencyInfo::default()
+                    .image_memory_barriers(std::slice::from_ref(&barrier)),
+            )
+        };
+    }
+
+    /// Recreates screen-sized images from their infos. The previous ones are retired, so
+    /// call [`Self::release_retired`] with the next frame.
     pub fn update_images(&mut self, indices: &[usize]) -> Result<()> {
-        for (i, info) in indices.iter().map(|&i| (i, &self.image_infos[i])) {
+        for &i in indices {
+            let info = self.image_infos[i];
             let image = Image::new(
                 &self.device,
-                info,
+                &info,
                 gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
             )?;
             let view = self.device.create_2d_view(&image.image, info.format)?;
@@ -375,10 +1007,16 @@ impl TextureArena {
                 .dst_array_element(i as _);
             unsafe { self.device.update_descriptor_sets(&[write], &[]) };
 
-            self.images[i].desctroy(&self.device);
-            unsafe { self.device.destroy_image_view(self.views[i], None) };
-            self.images[i] = image;
-            self.views[i] = view;
+            let old = self.textures[i].replace(Texture {
+                image,
+                view,
+                view_type: vk::ImageViewType::TYPE_2D,
+                extent: info.extent,
+            });
+            if let Some(old) = old {
+                self.retire(old, None);
+            }
+            self.depth_undefined |= i == DEPTH_IMAGE_IDX;
         }
 
         Ok(())
@@ -387,14 +1025,14 @@ impl TextureArena {
 
 impl Drop for TextureArena {
     fn drop(&mut self) {
+        self.retired.clear();
+        self.textures
+            .iter_mut()
+            .flatten()
+            .chain(self.pending.iter_mut().map(|pending| &mut pending.texture))
+            .chain(&mut self.placeholders)
+            .for_each(|texture| texture.destroy(&self.device));
         unsafe {
-            self.images.iter_mut().for_each(|image| {
-                image.desctroy(&self.device);
-            });
-
-            self.views
-                .iter()
-                .for_each(|&view| self.device.destroy_image_view(view, None));
             self.samplers
                 .iter()
                 .for_each(|&sampler| self.device.destroy_sampler(sampler, None));
@@ -0,0 +1,428 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use ddsfile::{Caps2, D3DFormat, Dds, DxgiFormat};
+
+use crate::NoiseDesc;
+
+pub const TEXTURE_EXTENSIONS: [&str; 8] =
+    ["png", "jpg", "jpeg", "hdr", "exr", "ktx2", "dds", "noise"];
+
+/// Texel data of a texture file, ready to be copied from a staging buffer.
+pub struct TextureData {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    /// Mip levels stored in `data`, a single level gets the rest of the chain
+    /// generated on upload.
+    pub levels: u32,
+    pub layers: u32,
+    /// `TYPE_2D`, `CUBE` or `TYPE_3D`, decides which bindless array the texture goes to.
+    pub view_type: vk::ImageViewType,
+    pub data: Vec<u8>,
+    pub regions: Vec<vk::BufferImageCopy>,
+}
+
+impl std::fmt::Debug for TextureData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextureData")
+            .field("format", &self.format)
+            .field("extent", &self.extent)
+            .field("levels", &self.levels)
+            .field("layers", &self.layers)
+            .field("view_type", &self.view_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TextureData {
+    pub fn new(format: vk::Format, extent: vk::Extent3D, layers: u32) -> Self {
+        Self {
+            format,
+            extent,
+            levels: 0,
+            layers,
+            view_type: vk::ImageViewType::TYPE_2D,
+            data: vec![],
+            regions: vec![],
+        }
+    }
+
+    pub fn from_levels<'a>(
+        format: vk::Format,
+        extent: vk::Extent3D,
+        levels: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Self {
+        let mut texture = Self::new(format, extent, 1);
+        for (mip_level, level) in levels.into_iter().enumerate() {
+            texture.push_region(mip_level as _, 0, 1, level);
+        }
+        texture
+    }
+
+    /// Appends tightly packed texels of `layer_count` consecutive layers of a mip level.
+    pub fn push_region(&mut self, mip_level: u32, base_layer: u32, layer_count: u32, data: &[u8]) {
+        // Offsets have to be a multiple of the texel block size, 16 covers every format
+        const OFFSET_ALIGNMENT: usize = 16;
+
+        self.data
+            .resize(self.data.len().next_multiple_of(OFFSET_ALIGNMENT), 0);
+        self.regions.push(
+            vk::BufferImageCopy::default()
+                .buffer_offset(self.data.len() as _)
+                .image_extent(mip_extent(self.extent, mip_level))
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level,
+                    base_array_layer: base_layer,
+                    layer_count,
+                }),
+        );
+        self.data.extend_from_slice(data);
+        self.levels = self.levels.max(mip_level + 1);
+    }
+}
+
+pub fn is_texture_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| TEXTURE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// GLSL constant holding the arena index of the texture,
+/// `textures/lava-rock.png` -> `LAVA_ROCK_TEX`.
+pub fn texture_constant_name(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name: String = stem
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name + "_TEX"
+}
+
+pub fn load_texture(path: &Path) -> Result<TextureData> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase);
+    let texture = match ext.as_deref() {
+        Some("ktx2") => load_ktx2(&std::fs::read(path)?),
+        Some("dds") => load_dds(&std::fs::read(path)?),
+        Some("noise") => std::fs::read_to_string(path)?
+            .parse::<NoiseDesc>()
+            .map(|desc| desc.generate()),
+        _ => load_image(path),
+    };
+    texture.with_context(|| format!("Failed to load texture {}", path.display()))
+}
+
+fn load_image(path: &Path) -> Result<TextureData> {
+    use image::ColorType;
+
+    let image = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()?;
+    let extent = vk::Extent3D {
+        width: image.width(),
+        height: image.height(),
+        depth: 1,
+    };
+    let texture = match image.color() {
+        ColorType::Rgb32F | ColorType::Rgba32F => {
+            let data = image.into_rgba32f().into_raw();
+            let data: &[u8] = bytemuck::cast_slice(&data);
+            TextureData::from_levels(vk::Format::R32G32B32A32_SFLOAT, extent, [data])
+        }
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+            let data = image.into_rgba16().into_raw();
+            let data: &[u8] = bytemuck::cast_slice(&data);
+            TextureData::from_levels(vk::Format::R16G16B16A16_UNORM, extent, [data])
+        }
+        _ => {
+            let data = image.into_rgba8().into_raw();
+            TextureData::from_levels(vk::Format::R8G8B8A8_SRGB, extent, [&data[..]])
+        }
+    };
+    Ok(texture)
+}
+
+fn load_ktx2(bytes: &[u8]) -> Result<TextureData> {
+    let reader = ktx2::Reader::new(bytes).context("Invalid KTX2 file")?;
+    let header = reader.header();
+    if let Some(scheme) = header.supercompression_scheme {
+        bail!("Supercompressed KTX2 files are not supported: {scheme:?}");
+    }
+    let format = header
+        .format
+        .context("KTX2 files without a Vulkan format are not supported")?;
+    let format = vk::Format::from_raw(format.0.get() as _);
+    let extent = vk::Extent3D {
+        width: header.pixel_width,
+        height: header.pixel_height.max(1),
+        depth: header.pixel_depth.max(1),
+    };
+
+    // Faces of every array element are stored next to each other
+    let layers = header.layer_count.max(1) * header.face_count.max(1);
+    let mut texture = TextureData::new(format, extent, layers);
+    texture.view_type = view_type(extent, header.face_count, layers)?;
+    for (mip_level, level) in reader.levels().enumerate() {
+        let expected = level_size(format, mip_extent(extent, mip_level as _))? * layers as usize;
+        if level.len() != expected {
+            bail!(
+                "Mip level {mip_level} holds {} bytes, expected {expected}",
+                level.len()
+            );
+        }
+        texture.push_region(mip_level as _, 0, layers, level);
+    }
+    Ok(texture)
+}
+
+fn load_dds(bytes: &[u8]) -> Result<TextureData> {
+    let dds = Dds::read(bytes)?;
+    let format = dds_format(&dds)?;
+    let extent = vk::Extent3D {
+        width: dds.get_width(),
+        height: dds.get_height(),
+        depth: dds.get_depth().max(1),
+    };
+
+    let faces = if dds.header.caps2.contains(Caps2::CUBEMAP) {
+        6
+    } else {
+        1
+    };
+    // Without a DX10 header `get_num_array_layers` already counts the faces of a cube map
+    let layers = dds.header10.as_ref().map_or(1, |h| h.array_size.max(1)) * faces;
+    let mut texture = TextureData::new(format, extent, layers);
+    texture.view_type = view_type(extent, faces, layers)?;
+
+    // Every layer and cube face stores its own mip chain
+    let mut data = &dds.data[..];
+    for layer in 0..layers {
+        for mip_level in 0..dds.get_num_mipmap_levels().max(1) {
+            let size = level_size(format, mip_extent(extent, mip_level))?;
+            let Some((level, rest)) = data.split_at_checked(size) else {
+                bail!("Layer {layer} is shorter than its mip chain");
+            };
+            texture.push_region(mip_level, layer, 1, level);
+            data = rest;
+        }
+    }
+    Ok(texture)
+}
+
+fn view_type(extent: vk::Extent3D, faces: u32, layers: u32) -> Result<vk::ImageViewType> {
+    Ok(match (extent.depth > 1, faces) {
+        (false, 0 | 1) => vk::ImageViewType::TYPE_2D,
+        (false, 6) if extent.width == extent.height => vk::ImageViewType::CUBE,
+        (false, 6) => bail!("Cube map faces have to be square"),
+        (true, 0 | 1) if layers == 1 => vk::ImageViewType::TYPE_3D,
+        _ => bail!(
+            "Unsupported texture layout: depth {}, {faces} faces, {layers} layers",
+            extent.depth
+        ),
+    })
+}
+
+fn dds_format(dds: &Dds) -> Result<vk::Format> {
+    use vk::Format as F;
+    if let Some(format) = dds.get_dxgi_format() {
+        return Ok(match format {
+            DxgiFormat::R8_UNorm => F::R8_UNORM,
+            DxgiFormat::R8G8_UNorm => F::R8G8_UNORM,
+            DxgiFormat::R8G8B8A8_UNorm => F::R8G8B8A8_UNORM,
+            DxgiFormat::R8G8B8A8_UNorm_sRGB => F::R8G8B8A8_SRGB,
+            DxgiFormat::B8G8R8A8_UNorm => F::B8G8R8A8_UNORM,
+            DxgiFormat::B8G8R8A8_UNorm_sRGB => F::B8G8R8A8_SRGB,
+            DxgiFormat::R16_Float => F::R16_SFLOAT,
+            DxgiFormat::R16G16_Float => F::R16G16_SFLOAT,
+            DxgiFormat::R16G16B16A16_Float => F::R16G16B16A16_SFLOAT,
+            DxgiFormat::R32_Float => F::R32_SFLOAT,
+            DxgiFormat::R32G32_Float => F::R32G32_SFLOAT,
+            DxgiFormat::R32G32B32A32_Float => F::R32G32B32A32_SFLOAT,
+            DxgiFormat::BC1_UNorm => F::BC1_RGBA_UNORM_BLOCK,
+            DxgiFormat::BC1_UNorm_sRGB => F::BC1_RGBA_SRGB_BLOCK,
+            DxgiFormat::BC2_UNorm => F::BC2_UNORM_BLOCK,
+            DxgiFormat::BC2_UNorm_sRGB => F::BC2_SRGB_BLOCK,
+            DxgiFormat::BC3_UNorm => F::BC3_UNORM_BLOCK,
+            DxgiFormat::BC3_UNorm_sRGB => F::BC3_SRGB_BLOCK,
+            DxgiFormat::BC4_UNorm => F::BC4_UNORM_BLOCK,
+            DxgiFormat::BC4_SNorm => F::BC4_SNORM_BLOCK,
+            DxgiFormat::BC5_UNorm => F::BC5_UNORM_BLOCK,
+            DxgiFormat::BC5_SNorm => F::BC5_SNORM_BLOCK,
+            DxgiFormat::BC6H_UF16 => F::BC6H_UFLOAT_BLOCK,
+            DxgiFormat::BC6H_SF16 => F::BC6H_SFLOAT_BLOCK,
+            DxgiFormat::BC7_UNorm => F::BC7_UNORM_BLOCK,
+            DxgiFormat::BC7_UNorm_sRGB => F::BC7_SRGB_BLOCK,
+            format => bail!("Unsupported DDS format {format:?}"),
+        });
+    }
+    if let Some(format) = dds.get_d3d_format() {
+        return Ok(match format {
+            D3DFormat::L8 => F::R8_UNORM,
+            D3DFormat::A8B8G8R8 => F::R8G8B8A8_UNORM,
+            D3DFormat::A8R8G8B8 => F::B8G8R8A8_UNORM,
+            D3DFormat::R16F => F::R16_SFLOAT,
+            D3DFormat::G16R16F => F::R16G16_SFLOAT,
+            D3DFormat::A16B16G16R16F => F::R16G16B16A16_SFLOAT,
+            D3DFormat::R32F => F::R32_SFLOAT,
+            D3DFormat::G32R32F => F::R32G32_SFLOAT,
+            D3DFormat::A32B32G32R32F => F::R32G32B32A32_SFLOAT,
+            D3DFormat::DXT1 => F::BC1_RGBA_UNORM_BLOCK,
+            D3DFormat::DXT2 | D3DFormat::DXT3 => F::BC2_UNORM_BLOCK,
+            D3DFormat::DXT4 | D3DFormat::DXT5 => F::BC3_UNORM_BLOCK,
+            format => bail!("Unsupported DDS format {format:?}"),
+        });
+    }
+    bail!("DDS file has no known pixel format")
+}
+
+/// Side of the square texel block and its size in bytes.
+fn block_layout(format: vk::Format) -> Option<(u32, usize)> {
+    use vk::Format as F;
+    Some(match format {
+        F::R8_UNORM | F::R8_SNORM | F::R8_SRGB => (1, 1),
+        F::R8G8_UNORM | F::R8G8_SNORM | F::R16_UNORM | F::R16_SFLOAT => (1, 2),
+        F::R8G8B8A8_UNORM
+        | F::R8G8B8A8_SNORM
+        | F::R8G8B8A8_SRGB
+        | F::B8G8R8A8_UNORM
+        | F::B8G8R8A8_SRGB
+        | F::A2B10G10R10_UNORM_PACK32
+        | F::B10G11R11_UFLOAT_PACK32
+        | F::E5B9G9R9_UFLOAT_PACK32
+        | F::R16G16_UNORM
+        | F::R16G16_SFLOAT
+        | F::R32_SFLOAT => (1, 4),
+        F::R16G16B16A16_UNORM | F::R16G16B16A16_SFLOAT | F::R32G32_SFLOAT => (1, 8),
+        F::R32G32B32A32_SFLOAT => (1, 16),
+        F::BC1_RGB_UNORM_BLOCK
+        | F::BC1_RGB_SRGB_BLOCK
+        | F::BC1_RGBA_UNORM_BLOCK
+        | F::BC1_RGBA_SRGB_BLOCK
+        | F::BC4_UNORM_BLOCK
+        | F::BC4_SNORM_BLOCK => (4, 8),
+        F::BC2_UNORM_BLOCK
+        | F::BC2_SRGB_BLOCK
+        | F::BC3_UNORM_BLOCK
+        | F::BC3_SRGB_BLOCK
+        | F::BC5_UNORM_BLOCK
+        | F::BC5_SNORM_BLOCK
+        | F::BC6H_UFLOAT_BLOCK
+        | F::BC6H_SFLOAT_BLOCK
+        | F::BC7_UNORM_BLOCK
+        | F::BC7_SRGB_BLOCK => (4, 16),
+        _ => return None,
+    })
+}
+
+/// Byte size of a single layer of a mip level.
+fn level_size(format: vk::Format, extent: vk::Extent3D) -> Result<usize> {
+    let (block, block_size) =
+        block_layout(format).with_context(|| format!("Unsupported texture format {format:?}"))?;
+    let blocks = extent.width.div_ceil(block) * extent.height.div_ceil(block) * extent.depth;
+    Ok(blocks as usize * block_size)
+}
+
+pub fn mip_extent(extent: vk::Extent3D, mip_level: u32) -> vk::Extent3D {
+    vk::Extent3D {
+        width: (extent.width >> mip_level).max(1),
+        height: (extent.height >> mip_level).max(1),
+        depth: (extent.depth >> mip_level).max(1),
+    }
+}
+
+pub fn mip_count(extent: vk::Extent3D) -> u32 {
+    let largest = extent.width.max(extent.height).max(extent.depth).max(1);
+    u32::BITS - largest.leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use ddsfile::{AlphaMode, D3D10ResourceDimension, NewD3dParams, NewDxgiParams};
+
+    use super::*;
+
+    /// 4x4 RGBA8 with its whole chain: 64 + 16 + 4 bytes per face.
+    const FACE_SIZE: usize = 84;
+
+    fn cube_bytes(mut dds: Dds, faces: usize) -> Vec<u8> {
+        dds.data = (0..faces * FACE_SIZE)
+            .map(|i| (i / FACE_SIZE) as u8)
+            .collect();
+        let mut bytes = vec![];
+        dds.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn check_cube(texture: &TextureData, faces: u32) {
+        assert_eq!(texture.view_type, vk::ImageViewType::CUBE);
+        assert_eq!(texture.layers, faces);
+        assert_eq!(texture.levels, 3);
+        assert_eq!(texture.regions.len(), faces as usize * 3);
+        for (face, regions) in texture.regions.chunks(3).enumerate() {
+            for (mip_level, region) in regions.iter().enumerate() {
+                let subresource = region.image_subresource;
+                assert_eq!(subresource.base_array_layer, face as u32);
+                assert_eq!(subresource.mip_level, mip_level as u32);
+                assert_eq!(texture.data[region.buffer_offset as usize], face as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn legacy_dds_cube_map() {
+        let dds = Dds::new_d3d(NewD3dParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: D3DFormat::A8B8G8R8,
+            mipmap_levels: Some(3),
+            caps2: Some(Caps2::CUBEMAP | Caps2::CUBEMAP_ALLFACES),
+        })
+        .unwrap();
+        let texture = load_dds(&cube_bytes(dds, 6)).unwrap();
+        assert_eq!(texture.format, vk::Format::R8G8B8A8_UNORM);
+        check_cube(&texture, 6);
+    }
+
+    #[test]
+    fn dx10_dds_cube_map() {
+        let dds = Dds::new_dxgi(NewDxgiParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: DxgiFormat::R8G8B8A8_UNorm,
+            mipmap_levels: Some(3),
+            array_layers: Some(1),
+            caps2: Some(Caps2::CUBEMAP | Caps2::CUBEMAP_ALLFACES),
+            is_cubemap: true,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Unknown,
+        })
+        .unwrap();
+        check_cube(&load_dds(&cube_bytes(dds, 6)).unwrap(), 6);
+    }
+
+    #[test]
+    fn truncated_dds_cube_map() {
+        let dds = Dds::new_d3d(NewD3dParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: D3DFormat::A8B8G8R8,
+            mipmap_levels: Some(3),
+            caps2: Some(Caps2::CUBEMAP | Caps2::CUBEMAP_ALLFACES),
+        })
+        .unwrap();
+        assert!(load_dds(&cube_bytes(dds, 5)).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::{device::HostBuffer, Device, COLOR_SUBRESOURCE_MASK};
+
+pub type UploadId = u64;
+
+struct Upload {
+    id: UploadId,
+    /// Every level of the image is left in `TRANSFER_DST_OPTIMAL`.
+    image: vk::Image,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    _staging: HostBuffer,
+}
+
+/// Copies staged textures on the dedicated transfer queue without blocking the renderer.
+/// Finished uploads are released to the main queue family and have to be acquired
+/// with [`TransferQueue::acquire_finished`] before use.
+pub struct TransferQueue {
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    uploads: Vec<Upload>,
+    next_id: UploadId,
+    device: Arc<Device>,
+}
+
+impl TransferQueue {
+    pub fn new(device: &Arc<Device>, queue: vk::Queue) -> Result<Self> {
+        let command_pool = unsafe {
+            device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                    .queue_family_index(device.transfer_queue_family_idx),
+                None,
+            )?
+        };
+        Ok(Self {
+            queue,
+            command_pool,
+            uploads: vec![],
+            next_id: 0,
+            device: device.clone(),
+        })
+    }
+
+    pub fn upload_image(
+        &mut self,
+        image: vk::Image,
+        regions: &[vk::BufferImageCopy],
+        data: &[u8],
+    ) -> Result<UploadId> {
+        let (src_family, dst_family) = self.queue_families();
+        self.submit(data, image, |device, cbuff, staging| {
+            let barrier = vk::ImageMemoryBarrier2::default()
+                .subresource_range(COLOR_SUBRESOURCE_MASK)
+                .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .image(image);
+            let release = vk::ImageMemoryBarrier2::default()
+                .subresource_range(COLOR_SUBRESOURCE_MASK)
+                .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family)
+                .image(image);
+            unsafe {
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default()
+                        .image_memory_barriers(std::slice::from_ref(&barrier)),
+                );
+                device.cmd_copy_buffer_to_image(
+                    cbuff,
+                    staging,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    regions,
+                );
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default()
+                        .image_memory_barriers(std::slice::from_ref(&release)),
+                );
+            }
+        })
+    }
+
+    fn queue_families(&self) -> (u32, u32) {
+        (
+            self.device.transfer_queue_family_idx,
+            self.device.main_queue_family_idx,
+        )
+    }
+
+    fn submit(
+        &mut self,
+        data: &[u8],
+        image: vk::Image,
+        record: impl FnOnce(&Device, vk::CommandBuffer, vk::Buffer),
+    ) -> Result<UploadId> {
+        let mut staging = self.device.create_host_buffer(
+            data.len() as _,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            UsageFlags::UPLOAD,
+        )?;
+        staging.copy_from_slice(data);
+
+        let command_buffer = unsafe {
+            self.device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(self.command_pool)
+                    .command_buffer_count(1)
+                    .level(vk::CommandBufferLevel::PRIMARY),
+            )?[0]
+        };
+        let fence = unsafe {
+            self.device
+                .create_fence(&vk::FenceCreateInfo::default(), None)?
+        };
+        let upload = Upload {
+            id: self.next_id,
+            image,
+            command_buffer,
+            fence,
+            _staging: staging,
+        };
+
+        let res = unsafe {
+            self.device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+        }
+        .and_then(|_| {
+            record(&self.device, command_buffer, upload._staging.buffer);
+            unsafe { self.device.end_command_buffer(command_buffer) }
+        })
+        .and_then(|_| {
+            let submit_info =
+                vk::SubmitInfo::default().command_buffers(std::slice::from_ref(&command_buffer));
+            unsafe { self.device.queue_submit(self.queue, &[submit_info], fence) }
+        });
+        if let Err(err) = res {
+            upload.destroy(&self.device, &self.command_pool);
+            return Err(err.into());
+        }
+
+        self.next_id += 1;
+        self.uploads.push(upload);
+        Ok(self.next_id - 1)
+    }
+
+    /// Records the acquire of every finished upload into `command_buffer` and returns
+    /// their ids, the ones still in flight are left for a later call.
+    pub fn acquire_finished(&mut self, command_buffer: &vk::CommandBuffer) -> Vec<UploadId> {
+        let (src_family, dst_family) = self.queue_families();
+        let mut barriers = vec![];
+        let mut finished = vec![];
+        self.uploads.retain(|upload| {
+            let status = unsafe { self.device.get_fence_status(upload.fence) };
+            if status != Ok(true) {
+                return true;
+            }
+            barriers.push(
+                vk::ImageMemoryBarrier2::default()
+                    .subresource_range(COLOR_SUBRESOURCE_MASK)
+                    .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                    .dst_access_mask(
+                        vk::AccessFlags2::TRANSFER_READ | vk::AccessFlags2::TRANSFER_WRITE,
+                    )
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)
+                    .image(upload.image),
+            );
+            finished.push(upload.id);
+            upload.destroy(&self.device, &self.command_pool);
+            false
+        });
+
+        if !finished.is_empty() {
+            let dependency_info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+            unsafe {
+                self.device
+                    .cmd_pipeline_barrier2(*command_buffer, &dependency_info)
+            };
+        }
+        finished
+    }
+
+    /// Blocks until every upload in flight has finished.
+    pub fn wait_idle(&self) -> Result<()> {
+        let fences: Vec<_> = self.uploads.iter().map(|upload| upload.fence).collect();
+        if !fences.is_empty() {
+            unsafe { self.device.wait_for_fences(&fences, true, u64::MAX)? };
+        }
+        Ok(())
+    }
+}
+
+impl Upload {
+    /// Staging memory goes away with the upload, so it must have finished or never been submitted.
+    fn destroy(&self, device: &Device, pool: &vk::CommandPool) {
+        unsafe {
+            device.destroy_fence(self.fence, None);
+            device.free_command_buffers(*pool, &[self.command_buffer]);
+        }
+    }
+}
+
+impl Drop for TransferQueue {
+    fn drop(&mut self) {
+        if let Err(err) = self.wait_idle() {
+            log::error!("Failed to wait for uploads: {err}");
+        }
+        for upload in self.uploads.drain(..) {
+            upload.destroy(&self.device, &self.command_pool);
+        }
+        unsafe { self.device.destroy_command_pool(self.command_pool, None) };
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use crossbeam_channel::{Receiver, TryRecvError};
+
+use crate::TextureData;
+
+pub const VIDEO_EXTENSIONS: [&str; 6] = ["mp4", "mkv", "webm", "mov", "avi", "gif"];
+
+/// Decoded frames buffered ahead of the timeline.
+const DECODE_AHEAD: usize = 4;
+/// Skipping more frames than this restarts the decoder at the new position.
+const SEEK_THRESHOLD: u64 = 30;
+
+pub fn is_video_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VideoInfo {
+    pub extent: vk::Extent2D,
+    pub frame_rate: f64,
+    pub duration: f64,
+}
+
+impl VideoInfo {
+    pub fn probe(path: &Path) -> Result<Self> {
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0"])
+            .args([
+                "-show_entries",
+                "stream=width,height,r_frame_rate:format=duration",
+            ])
+            .args(["-of", "default=noprint_wrappers=1"])
+            .arg(path)
+            .output()
+            .context("Failed to run ffprobe")?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        let (mut width, mut height, mut frame_rate, mut duration) = (None, None, None, None);
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            match line.split_once('=') {
+                Some(("width", value)) => width = value.parse().ok(),
+                Some(("height", value)) => height = value.parse().ok(),
+                Some(("duration", value)) => duration = value.parse().ok(),
+                Some(("r_frame_rate", value)) => {
+                    frame_rate = match value.split_once('/') {
+                        Some((num, den)) => num
+                            .parse::<f64>()
+                            .ok()
+                            .zip(den.parse::<f64>().ok())
+                            .map(|(num, den)| num / den),
+                        None => value.parse().ok(),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (Some(width), Some(height)) = (width, height) else {
+            bail!("{} has no video stream", path.display());
+        };
+        let frame_rate = frame_rate
+            .filter(|fps: &f64| fps.is_finite() && *fps > 0.)
+            .unwrap_or(30.);
+        Ok(Self {
+            extent: vk::Extent2D { width, height },
+            frame_rate,
+            duration: duration
+                .filter(|d: &f64| *d > 0.)
+                .unwrap_or(1. / frame_rate),
+        })
+    }
+
+    pub fn frame_size(&self) -> usize {
+        (self.extent.width * self.extent.height * 4) as usize
+    }
+
+    fn frame_count(&self) -> u64 {
+        ((self.duration * self.frame_rate) as u64).max(1)
+    }
+}
+
+/// ffmpeg process streaming raw RGBA frames from a position of the file.
+struct Decoder {
+    process: Child,
+    frames: Receiver<Vec<u8>>,
+    next_frame: u64,
+}
+
+impl Decoder {
+    fn spawn(path: &Path, info: &VideoInfo, first_frame: u64) -> Result<Self> {
+        let start = first_frame as f64 / info.frame_rate;
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-v", "error", "-nostdin"])
+            .args(["-ss", &format!("{start:.3}")])
+            .arg("-i")
+            .arg(path)
+            .args(["-an", "-vf", &format!("fps={}", info.frame_rate)])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "pipe:"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            const WINAPI_UM_WINBASE_CREATE_NO_WINDOW: u32 = 0x08000000;
+            command.creation_flags(WINAPI_UM_WINBASE_CREATE_NO_WINDOW);
+        }
+
+        let mut process = command.spawn().context("Failed to spawn ffmpeg")?;
+        let mut stdout = process.stdout.take().unwrap();
+        let frame_size = info.frame_size();
+        let (tx, rx) = crossbeam_channel::bounded(DECODE_AHEAD);
+        std::thread::spawn(move || loop {
+            let mut frame = vec![0; frame_size];
+            if stdout.read_exact(&mut frame).is_err() || tx.send(frame).is_err() {
+                break;
+            }
+        });
+
+        Ok(Self {
+            process,
+            frames: rx,
+            next_frame: first_frame,
+        })
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Video file played into a texture slot, following `PushConstant::time`.
+pub struct VideoTexture {
+    pub slot: u32,
+    pub info: VideoInfo,
+    path: PathBuf,
+    decoder: Option<Decoder>,
+    current_frame: Option<u64>,
+    /// First frame past the end of the stream, it can be shorter than the reported duration.
+    end_frame: Option<u64>,
+}
+
+impl VideoTexture {
+    pub fn new(path: &Path, info: VideoInfo, slot: u32) -> Self {
+        Self {
+            slot,
+            info,
+            path: path.to_path_buf(),
+            decoder: None,
+            current_frame: None,
+            end_frame: None,
+        }
+    }
+
+    /// Texture with the layout of the video frames.
+    pub fn texture_data(&self, texels: &[u8]) -> TextureData {
+        let extent = vk::Extent3D {
+            width: self.info.extent.width,
+            height: self.info.extent.height,
+            depth: 1,
+        };
+        TextureData::from_levels(vk::Format::R8G8B8A8_SRGB, extent, [texels])
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the texels of the frame shown at `time` if it differs from the uploaded one.
+    /// Playback loops over the file, seeking and pausing follow the timeline.
+    pub fn frame_at(&mut self, time: f32) -> Result<Option<Vec<u8>>> {
+        let target = (time as f64 * self.info.frame_rate) as u64 % self.info.frame_count();
+        if self.current_frame == Some(target) {
+            return Ok(None);
+        }
+        if self.decoder.is_none() && self.end_frame.is_some_and(|end| target >= end) {
+            return Ok(None);
+        }
+
+        let restart = match &self.decoder {
+            Some(decoder) => {
+                target < decoder.next_frame || target > decoder.next_frame + SEEK_THRESHOLD
+            }
+            None => true,
+        };
+        if restart {
+            self.decoder = Some(Decoder::spawn(&self.path, &self.info, target)?);
+        }
+
+        let Some(decoder) = self.decoder.as_mut() else {
+            return Ok(None);
+        };
+        let mut frame = None;
+        let mut ended = false;
+        // Frames that are not decoded yet get shown on a later redraw
+        while decoder.next_frame <= target {
+            match decoder.frames.try_recv() {
+                Ok(texels) => {
+                    frame = Some(texels);
+                    self.current_frame = Some(decoder.next_frame);
+                    decoder.next_frame += 1;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    ended = true;
+                    break;
+                }
+            }
+        }
+        if ended {
+            self.end_frame = Some(decoder.next_frame);
+            self.decoder = None;
+        }
+        Ok(frame)
+    }
+}
@@ -4,13 +4,14 @@ use notify_debouncer_mini::{DebounceEventResult, DebouncedEventKind};
 use winit::event_loop::EventLoopProxy;
 
 use std::{
-    ffi::OsStr,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
-use crate::{ShaderSource, UserEvent};
+use crate::{
+    is_audio_file, is_shader_file, is_texture_file, is_video_file, ShaderSource, UserEvent,
+};
 
 use parking_lot::Mutex;
 
@@ -51,22 +52,26 @@ impl Watcher {
 fn watch_callback(proxy: EventLoopProxy<UserEvent>) -> impl FnMut(DebounceEventResult) {
     move |event| match event {
         Ok(events) => {
-            if let Some(path) = events
+            // One debounced batch may hold several files saved together
+            let paths: AHashSet<PathBuf> = events
                 .into_iter()
                 .filter(|e| e.kind == DebouncedEventKind::Any)
                 .map(|event| event.path)
-                .next()
-            {
-                if path.extension() == Some(OsStr::new("glsl"))
-                    || path.extension() == Some(OsStr::new("frag"))
-                    || path.extension() == Some(OsStr::new("vert"))
-                    || path.extension() == Some(OsStr::new("comp"))
-                {
-                    let _ = proxy
-                        .send_event(UserEvent::Glsl {
-                            path: path.canonicalize().unwrap(),
-                        })
-                        .map_err(|err| log::error!("Event Loop has been dropped: {err}"));
+                .collect();
+            for path in paths {
+                let event = if is_shader_file(&path) {
+                    let Ok(path) = path.canonicalize() else {
+                        continue;
+                    };
+                    UserEvent::Glsl { path }
+                } else if is_texture_file(&path) || is_video_file(&path) || is_audio_file(&path) {
+                    UserEvent::Texture { path }
+                } else {
+                    continue;
+                };
+                if let Err(err) = proxy.send_event(event) {
+                    log::error!("Event Loop has been dropped: {err}");
+                    return;
                 }
             }
         }